@@ -1,7 +1,18 @@
 import axios from 'axios';
-import { Workload, Settings } from './types';
+import { Workload, Settings, ProgressEvent, Job, Ignore, ReleaseNote, ScheduleInfo } from './types';
 
-const API_URL = '/api';
+// Injected by the server when slackwatch is served below a base path
+export const BASE_PATH: string = (window as any).__SLACKWATCH_BASE_PATH__ || '';
+const API_URL = `${BASE_PATH}/api`;
+
+// Attach a bearer token when one has been stored, for deployments using token or JWT auth
+axios.interceptors.request.use((config) => {
+  const token = localStorage.getItem('slackwatch_token');
+  if (token) {
+    config.headers.Authorization = `Bearer ${token}`;
+  }
+  return config;
+});
 
 export const api = {
   // Workloads
@@ -10,16 +21,100 @@ export const api = {
     return response.data;
   },
 
+  // Update and upgrade run as background jobs, these resolve once the job has finished
   updateWorkload: async (workload: Workload): Promise<void> => {
-    await axios.post(`${API_URL}/workloads/update`, workload);
+    const response = await axios.post(`${API_URL}/workloads/update`, workload);
+    await api.waitForJob(response.data.job_id);
   },
 
   upgradeWorkload: async (workload: Workload): Promise<void> => {
-    await axios.post(`${API_URL}/workloads/upgrade`, workload);
+    const response = await axios.post(`${API_URL}/workloads/upgrade`, workload);
+    await api.waitForJob(response.data.job_id);
+  },
+
+  refreshAll: async (bypassCache = false): Promise<number> => {
+    const response = await axios.post(`${API_URL}/workloads/refresh-all`, null, {
+      params: bypassCache ? { bypass_cache: true } : {},
+    });
+    return response.data.job_id;
+  },
+
+  // Releases between the current and latest version, stored while scanning
+  getReleaseNotes: async (workload: Workload): Promise<ReleaseNote[]> => {
+    const response = await axios.get(`${API_URL}/workloads/${workload.namespace}/${workload.name}/release-notes`);
+    return response.data;
+  },
+
+  // Ignores, a version, a version range or all updates until a date
+  ignoreWorkload: async (
+    workload: Workload,
+    ignore: { version?: string; version_range?: string; until?: string },
+  ): Promise<Ignore> => {
+    const response = await axios.post(
+      `${API_URL}/workloads/${workload.namespace}/${workload.name}/ignore`,
+      ignore,
+    );
+    return response.data;
+  },
+
+  getIgnores: async (workload: Workload): Promise<Ignore[]> => {
+    const response = await axios.get(`${API_URL}/workloads/${workload.namespace}/${workload.name}/ignores`);
+    return response.data;
+  },
+
+  deleteIgnore: async (workload: Workload, id: number): Promise<void> => {
+    await axios.delete(`${API_URL}/workloads/${workload.namespace}/${workload.name}/ignores/${id}`);
+  },
+
+  // Jobs
+  getJobs: async (): Promise<Job[]> => {
+    const response = await axios.get(`${API_URL}/jobs`);
+    return response.data;
+  },
+
+  getJob: async (jobId: number): Promise<Job> => {
+    const response = await axios.get(`${API_URL}/jobs/${jobId}`);
+    return response.data;
   },
 
-  refreshAll: async (): Promise<void> => {
-    await axios.post(`${API_URL}/workloads/refresh-all`);
+  waitForJob: async (jobId: number): Promise<void> => {
+    let error: string | undefined;
+    await api.streamJobEvents(jobId, (event) => {
+      if (event.event === 'job_finished' && !event.success) {
+        error = event.error ?? 'Job failed';
+      }
+    });
+    if (error) throw new Error(error);
+  },
+
+  // Stream job progress over Server-Sent Events. Uses fetch rather than EventSource
+  // so the Authorization header can be sent. Resolves when the stream ends.
+  streamJobEvents: async (jobId: number, onEvent: (event: ProgressEvent) => void): Promise<void> => {
+    const token = localStorage.getItem('slackwatch_token');
+    const response = await fetch(`${API_URL}/jobs/${jobId}/events`, {
+      headers: token ? { Authorization: `Bearer ${token}` } : {},
+    });
+    if (!response.ok || !response.body) {
+      throw new Error(`Failed to stream job ${jobId}: ${response.status}`);
+    }
+    const reader = response.body.getReader();
+    const decoder = new TextDecoder();
+    let buffer = '';
+    for (;;) {
+      const { done, value } = await reader.read();
+      if (done) return;
+      buffer += decoder.decode(value, { stream: true });
+      const messages = buffer.split('\n\n');
+      buffer = messages.pop() ?? '';
+      for (const message of messages) {
+        const data = message
+          .split('\n')
+          .filter((line) => line.startsWith('data:'))
+          .map((line) => line.slice(5).trim())
+          .join('\n');
+        if (data) onEvent(JSON.parse(data));
+      }
+    }
   },
 
   // Settings
@@ -36,5 +131,10 @@ export const api = {
       return JSON.stringify(response.data);
     }
     return response.data;
+  },
+
+  getSchedules: async (): Promise<ScheduleInfo[]> => {
+    const response = await axios.get(`${API_URL}/schedules`);
+    return response.data;
   }
 };
@@ -1,19 +1,46 @@
 import React, { useState, useEffect } from 'react';
+import axios from 'axios';
 import { api } from '../api';
 import { Link } from 'react-router-dom';
+import { ProgressEvent } from '../types';
+
+const describe = (event: ProgressEvent): string | null => {
+  switch (event.event) {
+    case 'started':
+      return `${event.namespace}/${event.name}: checking ${event.image}`;
+    case 'tags_fetched':
+      return `${event.namespace}/${event.name}: fetched ${event.count} tags`;
+    case 'update_found':
+      return `${event.namespace}/${event.name}: update available ${event.current_version} -> ${event.latest_version}`;
+    case 'error':
+      return `${event.namespace}/${event.name}: error ${event.error}`;
+    case 'finished':
+      return `${event.namespace}/${event.name}: done`;
+    default:
+      return null;
+  }
+};
 
 export const RefreshAll: React.FC = () => {
   const [isLoading, setIsLoading] = useState(true);
   const [error, setError] = useState<string | null>(null);
-  const [isComplete, setIsComplete] = useState(false);
+  const [events, setEvents] = useState<ProgressEvent[]>([]);
 
   useEffect(() => {
     const refreshAllWorkloads = async () => {
       try {
-        await api.refreshAll();
-        setIsComplete(true);
+        const jobId = await api.refreshAll();
+        await api.streamJobEvents(jobId, (event) => {
+          setEvents((previous) => [...previous, event]);
+          if (event.event === 'job_finished' && !event.success) {
+            setError(event.error ?? 'Failed to refresh workloads');
+          }
+        });
       } catch (err) {
-        setError('Failed to refresh workloads');
+        // 409 when a full scan is already running
+        setError(axios.isAxiosError(err) && err.response?.data?.error
+          ? err.response.data.error
+          : 'Failed to refresh workloads');
         console.error(err);
       } finally {
         setIsLoading(false);
@@ -23,16 +50,22 @@ export const RefreshAll: React.FC = () => {
     refreshAllWorkloads();
   }, []);
 
-  if (isLoading) return <div>Refreshing all workloads...</div>;
-
-  if (error) return <div>Error: {error}</div>;
+  const progress = events.map(describe).filter((line): line is string => line !== null);
 
   return (
     <div>
-      <div>Refreshed</div>
-      <br />
-      <br />
-      <Link to="/">Go back to Home</Link>
+      <div>{isLoading ? 'Refreshing all workloads...' : error ? `Error: ${error}` : 'Refreshed'}</div>
+      <ul className="refresh-progress">
+        {progress.map((line, index) => (
+          <li key={index}>{line}</li>
+        ))}
+      </ul>
+      {!isLoading && (
+        <>
+          <br />
+          <Link to="/">Go back to Home</Link>
+        </>
+      )}
     </div>
   );
 };
@@ -1,5 +1,5 @@
 import React, { useState, useEffect } from 'react';
-import { Workload } from '../types';
+import { ScheduleInfo, Workload } from '../types';
 import { api } from '../api';
 import { Link } from 'react-router-dom';
 
@@ -9,6 +9,7 @@ interface SystemInfoCardProps {
 
 export const SystemInfoCard: React.FC<SystemInfoCardProps> = ({ workloads }) => {
   const [nextSchedule, setNextSchedule] = useState<string | null>(null);
+  const [schedules, setSchedules] = useState<ScheduleInfo[]>([]);
   const [isLoading, setIsLoading] = useState(true);
   const [error, setError] = useState<string | null>(null);
 
@@ -32,6 +33,10 @@ export const SystemInfoCard: React.FC<SystemInfoCardProps> = ({ workloads }) =>
     };
 
     fetchNextSchedule();
+    // Only listed when workloads can pick a named schedule, the next run covers the default
+    api.getSchedules()
+      .then(setSchedules)
+      .catch((err) => console.error('Failed to fetch schedules', err));
   }, []);
 
   return (
@@ -46,6 +51,11 @@ export const SystemInfoCard: React.FC<SystemInfoCardProps> = ({ workloads }) =>
       ) : (
         <>
           <div className="system-info-entry">Next Run: {nextSchedule}</div>
+          {schedules.length > 1 && schedules.map((schedule) => (
+            <div className="system-info-entry" key={schedule.name}>
+              Schedule {schedule.name} ({schedule.cron}): {schedule.next_run ?? 'no upcoming run'}
+            </div>
+          ))}
           <div className="system-info-entry">
             <Link to="/refresh-all">Click to Run Now</Link>
           </div>
@@ -1,5 +1,5 @@
 import React, { useState } from 'react';
-import { Workload } from '../types';
+import { ReleaseNote, Workload } from '../types';
 import { api } from '../api';
 
 interface WorkloadCardProps {
@@ -9,6 +9,7 @@ interface WorkloadCardProps {
 
 export const WorkloadCard: React.FC<WorkloadCardProps> = ({ workload, onUpdate }) => {
   const [isLoading, setIsLoading] = useState(false);
+  const [releaseNotes, setReleaseNotes] = useState<ReleaseNote[] | null>(null);
 
   const handleRefresh = async () => {
     setIsLoading(true);
@@ -34,6 +35,39 @@ export const WorkloadCard: React.FC<WorkloadCardProps> = ({ workload, onUpdate }
     }
   };
 
+  // Ignore the offered version, or every update for a week, then rescan so the card reflects it
+  const handleIgnore = async (ignore: { version?: string; until?: string }) => {
+    setIsLoading(true);
+    try {
+      await api.ignoreWorkload(workload, ignore);
+      await api.updateWorkload(workload);
+      if (onUpdate) onUpdate();
+    } catch (error) {
+      console.error('Error ignoring update:', error);
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
+  // Releases between the running and latest version, loaded on first expand
+  const toggleReleaseNotes = async () => {
+    if (releaseNotes) {
+      setReleaseNotes(null);
+      return;
+    }
+    try {
+      setReleaseNotes(await api.getReleaseNotes(workload));
+    } catch (error) {
+      console.error('Error loading release notes:', error);
+    }
+  };
+
+  const snoozeUntil = () => {
+    const until = new Date();
+    until.setDate(until.getDate() + 7);
+    return until.toISOString().slice(0, 10);
+  };
+
   return (
     <div className={workload.update_available === 'Available' ? 'workload-card-update-available' : 'workload-card'}>
       <div className="workload-name">{workload.name}</div>
@@ -47,11 +81,49 @@ export const WorkloadCard: React.FC<WorkloadCardProps> = ({ workload, onUpdate }
       <div className="workload-namespace">Namespace: {workload.namespace}</div>
       <div className="workload-version">Current Tag {workload.current_version}</div>
       <div className="workload-image">Image: {workload.image}</div>
+      {workload.schedule && (
+        <div className="workload-schedule">Schedule: {workload.schedule}</div>
+      )}
+      {workload.current_metadata?.version && workload.current_metadata.version !== workload.current_version && (
+        <div className="workload-image-version">Image Version: {workload.current_metadata.version}</div>
+      )}
+      {workload.current_metadata?.created && (
+        <div className="workload-image-created">Built: {workload.current_metadata.created}</div>
+      )}
+      {workload.current_metadata?.revision && (
+        <div className="workload-image-revision">Revision: {workload.current_metadata.revision.slice(0, 12)}</div>
+      )}
+      {workload.current_metadata?.licenses && (
+        <div className="workload-image-licenses">Licenses: {workload.current_metadata.licenses}</div>
+      )}
       <div className="workload-last-scanned">Last Scanned: {workload.last_scanned}</div>
 
       {workload.update_available === 'Available' && (
         <>
           <div className="workload-latest-version">Latest Version Available: {workload.latest_version}</div>
+          {workload.latest_metadata?.created && (
+            <div className="workload-latest-created">Latest Built: {workload.latest_metadata.created}</div>
+          )}
+          {workload.changelog_url && (
+            <div className="workload-changelog">
+              <a href={workload.changelog_url} target="_blank" rel="noopener noreferrer">Release notes</a>
+              {' '}
+              <button onClick={toggleReleaseNotes} className="release-notes-button">
+                {releaseNotes ? 'Hide releases' : 'Show releases'}
+              </button>
+            </div>
+          )}
+          {releaseNotes && (
+            <ul className="workload-release-notes">
+              {releaseNotes.length === 0 && <li>No releases stored</li>}
+              {releaseNotes.map((note) => (
+                <li key={note.version}>
+                  <a href={note.url} target="_blank" rel="noopener noreferrer">{note.title || note.version}</a>
+                  {note.published_at && ` (${note.published_at.slice(0, 10)})`}
+                </li>
+              ))}
+            </ul>
+          )}
           <br />
           <button
             onClick={handleUpgrade}
@@ -60,6 +132,20 @@ export const WorkloadCard: React.FC<WorkloadCardProps> = ({ workload, onUpdate }
           >
             Upgrade
           </button>
+          <button
+            onClick={() => handleIgnore({ version: workload.latest_version })}
+            className="ignore-button"
+            disabled={isLoading}
+          >
+            Ignore {workload.latest_version}
+          </button>
+          <button
+            onClick={() => handleIgnore({ until: snoozeUntil() })}
+            className="snooze-button"
+            disabled={isLoading}
+          >
+            Snooze 7 days
+          </button>
         </>
       )}
     </div>
@@ -2,11 +2,13 @@ import React from 'react';
 import ReactDOM from 'react-dom/client';
 import { BrowserRouter, createBrowserRouter, RouterProvider } from 'react-router-dom';
 import App from './App';
+import { BASE_PATH } from './api';
 
 // Create router with future flags enabled
 const router = createBrowserRouter(
   [{ path: "*", element: <App /> }],
   {
+    basename: BASE_PATH || '/',
     future: {
       v7_startTransition: true,
       v7_relativeSplatPath: true
@@ -6,6 +6,26 @@ export interface Workload {
   latest_version: string;
   last_scanned: string;
   update_available: 'Available' | 'NotAvailable' | 'Unknown';
+  source?: string;
+  changelog_url?: string;
+  current_metadata?: ImageMetadata;
+  latest_metadata?: ImageMetadata;
+  schedule?: string;
+}
+
+export interface ScheduleInfo {
+  name: string;
+  cron: string;
+  next_run?: string;
+}
+
+// Standard org.opencontainers.image.* labels
+export interface ImageMetadata {
+  version?: string;
+  source?: string;
+  revision?: string;
+  created?: string;
+  licenses?: string;
 }
 
 export interface Settings {
@@ -14,6 +34,7 @@ export interface Settings {
     data_dir: string;
     run_at_startup: boolean;
   };
+  schedules?: { name: string; cron: string }[];
   gitops?: {
     name: string;
     repository_url: string;
@@ -23,3 +44,49 @@ export interface Settings {
     discord_webhook_url?: string;
   };
 }
+
+export type ProgressEvent = {
+  job_id: number;
+  seq: number;
+  timestamp: string;
+} & (
+  | { event: 'job_started' }
+  | { event: 'started'; namespace: string; name: string; image: string }
+  | { event: 'tags_fetched'; namespace: string; name: string; count: number }
+  | { event: 'update_found'; namespace: string; name: string; current_version: string; latest_version: string }
+  | { event: 'error'; namespace: string; name: string; error: string }
+  | { event: 'finished'; namespace: string; name: string }
+  | { event: 'rollout'; namespace: string; name: string; status: string }
+  | { event: 'job_finished'; success: boolean; error?: string }
+);
+
+export interface Job {
+  id: number;
+  job_type: 'scan-all' | 'scan-one' | 'upgrade';
+  state: 'running' | 'succeeded' | 'failed';
+  target?: string;
+  created_at: string;
+  finished_at?: string;
+  result?: unknown;
+  error?: string;
+  log?: string[];
+}
+
+export interface Ignore {
+  id: number;
+  namespace: string;
+  name: string;
+  version?: string;
+  version_range?: string;
+  until?: string;
+  created_at: string;
+}
+
+export interface ReleaseNote {
+  source: string;
+  version: string;
+  title?: string;
+  url: string;
+  body?: string;
+  published_at?: string;
+}
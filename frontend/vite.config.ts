@@ -3,6 +3,8 @@ import react from '@vitejs/plugin-react';
 
 export default defineConfig({
   plugins: [react()],
+  // Relative asset URLs so the app can be served below a base path
+  base: './',
   server: {
     proxy: {
       '/api': {
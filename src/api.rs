@@ -1,158 +1,658 @@
 use warp::{Filter, Rejection, Reply};
 use warp::filters::cors::cors;
-use warp::http::Method;
+use warp::http::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use crate::models::models::Workload;
-use crate::config::Settings;
-use crate::services::workloads::{fetch_and_update_all_watched, update_single_workload};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::auth::auth::require;
+use crate::metrics::gather;
+use crate::services::jobs::{job_log, start_job, subscribe, JobError, ProgressKind};
+use futures::StreamExt;
+use tokio::sync::broadcast::error::RecvError;
+use crate::services::health::{liveness, readiness, HealthReport};
+use crate::models::models::{JobType, Role, Workload};
+use crate::config::{current_settings, Settings};
+use crate::services::workloads::{fetch_and_update_all_watched, find_workload, update_single_workload, WorkloadError};
 use crate::gitops::gitops::run_git_operations;
-use crate::services::scheduler::next_schedule_time;
-use crate::database::client::return_all_workloads;
+use crate::services::rollout::verify_rollout;
+use crate::services::scheduler::{next_run, schedule_overview};
+use crate::database::client::{delete_ignore, return_all_workloads, return_ignores, return_job, return_jobs};
+use crate::services::changelog::stored_release_notes;
+use crate::services::ignores::{create_ignore, IgnoreError};
+
+pub async fn start_api_server(settings: Settings) {
+    let auth = Arc::new(settings.auth);
+    log::info!("API auth mode: {:?}", auth.mode);
 
-pub async fn start_api_server() {
     // CORS configuration
     let cors = cors()
-        .allow_any_origin()
-        .allow_methods(&[Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers(vec!["Content-Type"]);
-
-    // API routes
-    let api = warp::path("api");
+        .allow_methods(&[Method::GET, Method::POST, Method::DELETE, Method::OPTIONS])
+        .allow_headers(vec!["Content-Type", "Authorization"]);
+    let cors = if auth.allowed_origins.is_empty() {
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(auth.allowed_origins.iter().map(String::as_str))
+    };
+    let viewer = || require(auth.clone(), Role::Viewer);
+    let maintainer = || require(auth.clone(), Role::Maintainer);
 
     // GET /api/workloads - Get all workloads
-    let get_workloads = api
-        .and(warp::path("workloads"))
+    let get_workloads = warp::path("workloads")
+        .and(warp::path::end())
         .and(warp::get())
+        .and(viewer())
         .and_then(handle_get_workloads);
 
-    // POST /api/workloads/update - Update a workload
-    let update_workload = api
-        .and(warp::path("workloads"))
+    // POST /api/workloads/update[?bypass_cache=true] - Update a workload
+    let update_workload = warp::path("workloads")
         .and(warp::path("update"))
+        .and(warp::path::end())
         .and(warp::post())
+        .and(maintainer())
+        .and(warp::query::<RefreshOptions>())
         .and(warp::body::json())
         .and_then(handle_update_workload);
 
     // POST /api/workloads/upgrade - Upgrade a workload
-    let upgrade_workload = api
-        .and(warp::path("workloads"))
+    let upgrade_workload = warp::path("workloads")
         .and(warp::path("upgrade"))
+        .and(warp::path::end())
         .and(warp::post())
+        .and(maintainer())
         .and(warp::body::json())
         .and_then(handle_upgrade_workload);
 
-    // POST /api/workloads/refresh-all - Refresh all workloads
-    let refresh_all = api
-        .and(warp::path("workloads"))
+    // POST /api/workloads/refresh-all[?bypass_cache=true] - Refresh all workloads
+    let refresh_all = warp::path("workloads")
         .and(warp::path("refresh-all"))
+        .and(warp::path::end())
         .and(warp::post())
+        .and(maintainer())
+        .and(warp::query::<RefreshOptions>())
         .and_then(handle_refresh_all);
 
+    // POST /api/workloads/{namespace}/{name}/ignore - Ignore a version, a version range or all updates until a date
+    let ignore_workload = warp::path!("workloads" / String / String / "ignore")
+        .and(warp::post())
+        .and(maintainer())
+        .and(warp::body::json())
+        .and_then(handle_ignore_workload);
+
+    // GET /api/workloads/{namespace}/{name}/ignores - Ignores of a workload
+    let get_ignores = warp::path!("workloads" / String / String / "ignores")
+        .and(warp::get())
+        .and(viewer())
+        .and_then(handle_get_ignores);
+
+    // DELETE /api/workloads/{namespace}/{name}/ignores/{id} - Remove an ignore
+    let delete_ignore = warp::path!("workloads" / String / String / "ignores" / i64)
+        .and(warp::delete())
+        .and(maintainer())
+        .and_then(handle_delete_ignore);
+
+    // GET /api/workloads/{namespace}/{name}/release-notes - Releases between current and latest version
+    let get_release_notes = warp::path!("workloads" / String / String / "release-notes")
+        .and(warp::get())
+        .and(viewer())
+        .and_then(handle_get_release_notes);
+
+    // GET /api/jobs - Most recent jobs
+    let get_jobs = warp::path("jobs")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(viewer())
+        .and_then(handle_get_jobs);
+
+    // GET /api/jobs/{id} - A single job including its log
+    let get_job = warp::path!("jobs" / i64)
+        .and(warp::get())
+        .and(viewer())
+        .and_then(handle_get_job);
+
+    // GET /api/jobs/{id}/events - Server-Sent Events with the progress of a job
+    let job_events = warp::path!("jobs" / i64 / "events")
+        .and(warp::get())
+        .and(viewer())
+        .and_then(handle_job_events);
+
     // GET /api/settings - Get settings
-    let get_settings = api
-        .and(warp::path("settings"))
+    let get_settings = warp::path("settings")
+        .and(warp::path::end())
         .and(warp::get())
+        .and(viewer())
         .and_then(handle_get_settings);
 
     // GET /api/settings/next-schedule-time - Get next schedule time
-    let get_next_schedule = api
-        .and(warp::path("settings"))
+    let get_next_schedule = warp::path("settings")
         .and(warp::path("next-schedule-time"))
+        .and(warp::path::end())
         .and(warp::get())
+        .and(viewer())
         .and_then(handle_get_next_schedule);
 
-    // Serve static files from the frontend/dist directory
-    let static_files = warp::fs::dir("frontend/dist");
+    // GET /api/schedules - Named schedules and their next run
+    let get_schedules = warp::path("schedules")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(viewer())
+        .and_then(handle_get_schedules);
+
+    // API routes, rejections below /api are turned into JSON errors so they
+    // never fall through to the SPA fallback
+    let api = warp::path("api").and(
+        get_workloads
+            .or(update_workload)
+            .or(upgrade_workload)
+            .or(refresh_all)
+            .or(ignore_workload)
+            .or(get_ignores)
+            .or(delete_ignore)
+            .or(get_release_notes)
+            .or(get_jobs)
+            .or(get_job)
+            .or(job_events)
+            .or(get_settings)
+            .or(get_next_schedule)
+            .or(get_schedules)
+            .recover(handle_rejection),
+    );
+
+    // GET /metrics - Prometheus metrics
+    let metrics = warp::path("metrics").and(
+        warp::path::end()
+            .and(warp::get())
+            .and(viewer())
+            .and_then(handle_metrics)
+            .recover(handle_rejection),
+    );
+
+    // GET /healthz and /readyz - Probes for Kubernetes, unauthenticated
+    let healthz = warp::path("healthz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(handle_healthz);
+    let readyz = warp::path("readyz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(handle_readyz);
+
+    // Serve static files from the configured directory
+    let system = settings.system;
+    let base_path = normalize_base_path(&system.base_path);
+    let index_path = Path::new(&system.static_dir).join("index.html");
+
+    // GET / - index.html with the base path injected, so the SPA can build its URLs
+    let index = {
+        let index_path = index_path.clone();
+        let base_path = base_path.clone();
+        warp::path::end()
+            .and(warp::get())
+            .and_then(move || handle_index(index_path.clone(), base_path.clone()))
+    };
+    let static_files = warp::fs::dir(system.static_dir.clone());
 
     // Fallback route for SPA - serve index.html for any other route
-    let spa_fallback = warp::any()
-        .and(warp::fs::file("frontend/dist/index.html"));
+    let spa_fallback = {
+        let base_path = base_path.clone();
+        warp::get().and_then(move || handle_index(index_path.clone(), base_path.clone()))
+    };
+
+    // Everything except probes and metrics is served below the base path
+    let prefix = base_path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .fold(warp::any().boxed(), |filter, segment| {
+            filter.and(warp::path(segment.to_string())).boxed()
+        });
+    let app = prefix.and(api.or(index).or(static_files).or(spa_fallback));
 
     // Combine all routes
-    let routes = get_workloads
-        .or(update_workload)
-        .or(upgrade_workload)
-        .or(refresh_all)
-        .or(get_settings)
-        .or(get_next_schedule)
-        .or(static_files)
-        .or(spa_fallback)
+    let routes = metrics
+        .or(healthz)
+        .or(readyz)
+        .or(app)
         .with(cors);
 
     // Start the server
-    log::info!("Starting API server on 0.0.0.0:8080");
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], 8080))
-        .await;
+    // Checked by validation before the server starts
+    let ip: IpAddr = match system.listen_address.parse() {
+        Ok(ip) => ip,
+        Err(e) => {
+            log::error!("Not starting API server, invalid listen_address {}: {}", system.listen_address, e);
+            return;
+        }
+    };
+    let addr = SocketAddr::new(ip, system.port);
+    let server = warp::serve(routes);
+    match (&system.tls_cert, &system.tls_key) {
+        (Some(cert), Some(key)) => {
+            log::info!("Starting API server on https://{}{}", addr, base_path);
+            server.tls().cert_path(cert).key_path(key).run(addr).await;
+        }
+        _ => {
+            log::info!("Starting API server on http://{}{}", addr, base_path);
+            server.run(addr).await;
+        }
+    }
 }
 
-async fn handle_get_workloads() -> Result<impl Reply, Rejection> {
-    match return_all_workloads() {
-        Ok(workloads) => Ok(warp::reply::json(&workloads)),
-        Err(e) => {
-            log::error!("Failed to get workloads: {}", e);
-            let error = json!({ "error": format!("Failed to get workloads: {}", e) });
-            Ok(warp::reply::json(&error))
+/// Normalize a configured base path to `/prefix` without a trailing slash, or empty.
+fn normalize_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+async fn handle_index(index_path: PathBuf, base_path: String) -> Result<impl Reply, Rejection> {
+    let index = tokio::fs::read_to_string(&index_path).await.map_err(|e| {
+        log::error!("Failed to read {:?}: {}", index_path, e);
+        warp::reject::not_found()
+    })?;
+    Ok(warp::reply::html(inject_base_path(&index, &base_path)))
+}
+
+// Point the index page at the base path, escaped for the attribute and the script
+fn inject_base_path(index: &str, base_path: &str) -> String {
+    let href = format!("{}/", base_path)
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    // A JSON string is a JS string literal, `<` is escaped so it cannot close the script
+    let script_value = serde_json::to_string(base_path)
+        .unwrap_or_else(|_| "\"\"".to_string())
+        .replace('<', "\\u003c");
+    let injected = format!(
+        "<head>\n    <base href=\"{}\" />\n    <script>window.__SLACKWATCH_BASE_PATH__ = {};</script>",
+        href, script_value
+    );
+    index.replacen("<head>", &injected, 1)
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    BadRequest(String),
+    Conflict(String),
+    Database(String),
+    Kubernetes(String),
+    Registry(String),
+    Gitops(String),
+    Scan(String),
+    Config(String),
+}
+
+impl warp::reject::Reject for ApiError {}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Kubernetes(_) | ApiError::Registry(_) | ApiError::Gitops(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            ApiError::Database(_) | ApiError::Scan(_) | ApiError::Config(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Database(_) => "database_error",
+            ApiError::Kubernetes(_) => "kubernetes_error",
+            ApiError::Registry(_) => "registry_error",
+            ApiError::Gitops(_) => "gitops_error",
+            ApiError::Scan(_) => "scan_error",
+            ApiError::Config(_) => "config_error",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::Unauthorized(m)
+            | ApiError::Forbidden(m)
+            | ApiError::NotFound(m)
+            | ApiError::BadRequest(m)
+            | ApiError::Conflict(m)
+            | ApiError::Database(m)
+            | ApiError::Kubernetes(m)
+            | ApiError::Registry(m)
+            | ApiError::Gitops(m)
+            | ApiError::Scan(m)
+            | ApiError::Config(m) => m,
         }
     }
 }
 
-async fn handle_update_workload(workload: Workload) -> Result<impl Reply, Rejection> {
-    match update_single_workload(workload).await {
-        Ok(_) => Ok(warp::reply::json(&json!({ "status": "success" }))),
-        Err(e) => {
-            log::error!("Failed to update workload: {}", e);
-            let error = json!({ "error": format!("Failed to update workload: {}", e) });
-            Ok(warp::reply::json(&error))
+impl From<WorkloadError> for ApiError {
+    fn from(e: WorkloadError) -> Self {
+        match e {
+            WorkloadError::NotFound(_) => ApiError::NotFound(e.to_string()),
+            WorkloadError::Kubernetes(_) => ApiError::Kubernetes(e.to_string()),
+            WorkloadError::Registry(_) => ApiError::Registry(e.to_string()),
+            WorkloadError::Database(_) => ApiError::Database(e.to_string()),
+            WorkloadError::InvalidAnnotation(_) => ApiError::Config(e.to_string()),
         }
     }
 }
 
-async fn handle_upgrade_workload(workload: Workload) -> Result<impl Reply, Rejection> {
-    match run_git_operations(workload).await {
-        Ok(_) => Ok(warp::reply::json(&json!({ "status": "success" }))),
-        Err(e) => {
-            log::error!("Failed to upgrade workload: {}", e);
-            let error = json!({ "error": format!("Failed to upgrade workload: {}", e) });
-            Ok(warp::reply::json(&error))
+impl From<IgnoreError> for ApiError {
+    fn from(e: IgnoreError) -> Self {
+        match e {
+            IgnoreError::Invalid(_) => ApiError::BadRequest(e.to_string()),
+            IgnoreError::Database(_) => ApiError::Database(e.to_string()),
         }
     }
 }
 
-async fn handle_refresh_all() -> Result<impl Reply, Rejection> {
-    match fetch_and_update_all_watched().await {
-        Ok(_) => Ok(warp::reply::json(&json!({ "status": "success" }))),
-        Err(e) => {
-            log::error!("Failed to refresh all workloads: {}", e);
-            let error = json!({ "error": format!("Failed to refresh all workloads: {}", e) });
-            Ok(warp::reply::json(&error))
+impl From<JobError> for ApiError {
+    fn from(e: JobError) -> Self {
+        match e {
+            JobError::AlreadyRunning(_) => ApiError::Conflict(e.to_string()),
+            JobError::Database(_) => ApiError::Database(e.to_string()),
         }
     }
 }
 
-async fn handle_get_settings() -> Result<impl Reply, Rejection> {
-    match Settings::new() {
-        Ok(settings) => Ok(warp::reply::json(&settings)),
-        Err(e) => {
-            log::error!("Failed to get settings: {}", e);
-            let error = json!({ "error": format!("Failed to get settings: {}", e) });
-            Ok(warp::reply::json(&error))
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    error: &'a str,
+}
+
+fn error_reply(status: StatusCode, code: &str, error: &str) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&ErrorBody { code, error }), status)
+}
+
+async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    if let Some(e) = err.find::<ApiError>() {
+        log::error!("API error: {:?}", e);
+        return Ok(error_reply(e.status(), e.code(), e.message()));
+    }
+    if err.is_not_found() {
+        return Ok(error_reply(StatusCode::NOT_FOUND, "not_found", "Not found"));
+    }
+    if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, "bad_request", &e.to_string()));
+    }
+    if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        return Ok(error_reply(StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", "Method not allowed"));
+    }
+    log::error!("Unhandled rejection: {:?}", err);
+    Ok(error_reply(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error"))
+}
+
+fn health_reply(report: HealthReport) -> warp::reply::WithStatus<warp::reply::Json> {
+    let status = if report.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    warp::reply::with_status(warp::reply::json(&report), status)
+}
+
+async fn handle_healthz() -> Result<impl Reply, Rejection> {
+    Ok(health_reply(liveness()))
+}
+
+async fn handle_readyz() -> Result<impl Reply, Rejection> {
+    Ok(health_reply(readiness().await))
+}
+
+async fn handle_metrics() -> Result<impl Reply, Rejection> {
+    let body = gather().map_err(|e| warp::reject::custom(ApiError::Database(format!("Failed to gather metrics: {}", e))))?;
+    Ok(warp::reply::with_header(body, "Content-Type", "text/plain; version=0.0.4"))
+}
+
+async fn handle_get_workloads() -> Result<impl Reply, Rejection> {
+    return_all_workloads()
+        .map(|workloads| warp::reply::json(&workloads))
+        .map_err(|e| warp::reject::custom(ApiError::Database(format!("Failed to get workloads: {}", e))))
+}
+
+fn job_started(job_id: i64) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&json!({ "status": "started", "job_id": job_id })),
+        StatusCode::ACCEPTED,
+    )
+}
+
+// Query parameters of the manual refresh endpoints
+#[derive(Debug, Default, Deserialize)]
+struct RefreshOptions {
+    /// Fetch tags from the registry even when a cached list is still fresh
+    #[serde(default)]
+    bypass_cache: bool,
+}
+
+async fn handle_update_workload(options: RefreshOptions, workload: Workload) -> Result<impl Reply, Rejection> {
+    // Look the workload up before accepting the job so unknown workloads still get a 404
+    let workload = find_workload(&workload)
+        .await
+        .map_err(|e| warp::reject::custom(ApiError::from(e)))?;
+    let target = format!("{}/{}", workload.namespace, workload.name);
+    let job = start_job(JobType::ScanOne, Some(&target))
+        .map_err(|e| warp::reject::custom(ApiError::from(e)))?;
+    let job_id = job.id;
+    tokio::spawn(async move {
+        let result = update_single_workload(workload, &job, options.bypass_cache).await.map_err(|e| e.to_string());
+        if let Err(e) = &result {
+            log::error!("Failed to update workload {}: {}", target, e);
+        }
+        job.finish(result.map(|_| job.summary()));
+    });
+    Ok(job_started(job_id))
+}
+
+async fn handle_upgrade_workload(workload: Workload) -> Result<impl Reply, Rejection> {
+    let target = format!("{}/{}", workload.namespace, workload.name);
+    let job = start_job(JobType::Upgrade, Some(&target))
+        .map_err(|e| warp::reject::custom(ApiError::from(e)))?;
+    let job_id = job.id;
+    tokio::spawn(async move {
+        let result = match run_git_operations(workload.clone()).await.map_err(|e| e.to_string()) {
+            Ok(()) => verify_rollout(&workload, &job).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            log::error!("Failed to upgrade workload {}: {}", target, e);
         }
+        job.finish(result);
+    });
+    Ok(job_started(job_id))
+}
+
+async fn handle_refresh_all(options: RefreshOptions) -> Result<impl Reply, Rejection> {
+    let job = start_job(JobType::ScanAll, None).map_err(|e| warp::reject::custom(ApiError::from(e)))?;
+    let job_id = job.id;
+    tokio::spawn(async move {
+        let result = fetch_and_update_all_watched(&job, options.bypass_cache).await;
+        if let Err(e) = &result {
+            log::error!("Failed to refresh all workloads: {}", e);
+        }
+        job.finish(result.map(|_| job.summary()));
+    });
+    Ok(job_started(job_id))
+}
+
+// Body of POST /api/workloads/{namespace}/{name}/ignore
+#[derive(Debug, Deserialize)]
+struct IgnoreRequest {
+    version: Option<String>,
+    version_range: Option<String>,
+    until: Option<String>,
+}
+
+async fn handle_ignore_workload(
+    namespace: String,
+    name: String,
+    request: IgnoreRequest,
+) -> Result<impl Reply, Rejection> {
+    let ignore = create_ignore(
+        &namespace,
+        &name,
+        request.version.as_deref(),
+        request.version_range.as_deref(),
+        request.until.as_deref(),
+    )
+    .map_err(|e| warp::reject::custom(ApiError::from(e)))?;
+    log::info!("Added ignore {:?}", ignore);
+    Ok(warp::reply::with_status(warp::reply::json(&ignore), StatusCode::CREATED))
+}
+
+async fn handle_get_ignores(namespace: String, name: String) -> Result<impl Reply, Rejection> {
+    return_ignores(&namespace, &name)
+        .map(|ignores| warp::reply::json(&ignores))
+        .map_err(|e| warp::reject::custom(ApiError::Database(format!("Failed to get ignores: {}", e))))
+}
+
+async fn handle_delete_ignore(namespace: String, name: String, id: i64) -> Result<impl Reply, Rejection> {
+    match delete_ignore(&namespace, &name, id) {
+        Ok(true) => Ok(warp::reply::json(&json!({ "status": "deleted" }))),
+        Ok(false) => Err(warp::reject::custom(ApiError::NotFound(format!(
+            "Ignore {} not found for {}/{}",
+            id, namespace, name
+        )))),
+        Err(e) => Err(warp::reject::custom(ApiError::Database(format!("Failed to delete ignore: {}", e)))),
     }
 }
 
-async fn handle_get_next_schedule() -> Result<impl Reply, Rejection> {
-    match Settings::new() {
-        Ok(settings) => {
-            let schedule_str = &settings.system.schedule;
-            let next_schedule = next_schedule_time(&schedule_str).await;
-            // Ensure we're returning a string, not an object
-            Ok(warp::reply::json(&next_schedule))
-        },
-        Err(e) => {
-            log::error!("Failed to get settings for next schedule: {}", e);
-            let error = json!({ "error": format!("Failed to get settings for next schedule: {}", e) });
-            Ok(warp::reply::json(&error))
+async fn handle_get_release_notes(namespace: String, name: String) -> Result<impl Reply, Rejection> {
+    let workloads = return_all_workloads()
+        .map_err(|e| warp::reject::custom(ApiError::Database(format!("Failed to get workloads: {}", e))))?;
+    let workload = workloads
+        .into_iter()
+        .find(|workload| workload.namespace == namespace && workload.name == name)
+        .ok_or_else(|| warp::reject::custom(ApiError::NotFound(format!("{}/{}", namespace, name))))?;
+    stored_release_notes(&workload)
+        .map(|notes| warp::reply::json(&notes))
+        .map_err(|e| warp::reject::custom(ApiError::Database(format!("Failed to get release notes: {}", e))))
+}
+
+async fn handle_get_jobs() -> Result<impl Reply, Rejection> {
+    return_jobs(50)
+        .map(|jobs| warp::reply::json(&jobs))
+        .map_err(|e| warp::reject::custom(ApiError::Database(format!("Failed to get jobs: {}", e))))
+}
+
+async fn handle_get_job(job_id: i64) -> Result<impl Reply, Rejection> {
+    let mut job = return_job(job_id)
+        .map_err(|e| warp::reject::custom(ApiError::Database(format!("Failed to get job: {}", e))))?
+        .ok_or_else(|| warp::reject::custom(ApiError::NotFound(format!("Job {} not found", job_id))))?;
+    // Running jobs only have their log in memory
+    if job.log.is_empty() {
+        job.log = job_log(job_id).unwrap_or_default();
+    }
+    Ok(warp::reply::json(&job))
+}
+
+async fn handle_job_events(job_id: i64) -> Result<impl Reply, Rejection> {
+    let (history, receiver) = subscribe(job_id)
+        .ok_or_else(|| warp::reject::custom(ApiError::NotFound(format!("Job {} not found", job_id))))?;
+    let finished = history
+        .iter()
+        .any(|event| matches!(event.kind, ProgressKind::JobFinished { .. }));
+    // Follow the broadcast until this job reports it has finished
+    let live = futures::stream::unfold(receiver.filter(|_| !finished), move |receiver| async move {
+        let mut receiver = receiver?;
+        loop {
+            match receiver.recv().await {
+                Ok(event) if event.job_id == job_id => {
+                    let done = matches!(event.kind, ProgressKind::JobFinished { .. });
+                    return Some((event, (!done).then_some(receiver)));
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("Event stream for job {} lagged by {} events", job_id, skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => return None,
+            }
         }
+    });
+    // Events are sent as unnamed messages, the JSON `event` field carries the type
+    let events = futures::stream::iter(history)
+        .chain(live)
+        .map(|event| warp::sse::Event::default().id(event.seq.to_string()).json_data(&event));
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+
+async fn handle_get_settings() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&*current_settings()))
+}
+
+async fn handle_get_next_schedule() -> Result<impl Reply, Rejection> {
+    let next_schedule = next_run(&current_settings())
+        .map(|next| format!("{:?}", next))
+        .ok_or_else(|| warp::reject::custom(ApiError::Config("No upcoming schedule".to_string())))?;
+    // Ensure we're returning a string, not an object
+    Ok(warp::reply::json(&next_schedule))
+}
+
+async fn handle_get_schedules() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&schedule_overview(&current_settings())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_base_path() {
+        assert_eq!(normalize_base_path(""), "");
+        assert_eq!(normalize_base_path("/"), "");
+        assert_eq!(normalize_base_path("slackwatch"), "/slackwatch");
+        assert_eq!(normalize_base_path("/tools/slackwatch/"), "/tools/slackwatch");
+    }
+
+    #[test]
+    fn test_inject_base_path() {
+        let index = "<html><head><title>Slackwatch</title></head></html>";
+        assert!(inject_base_path(index, "/slackwatch").contains(
+            "<base href=\"/slackwatch/\" />\n    <script>window.__SLACKWATCH_BASE_PATH__ = \"/slackwatch\";</script>"
+        ));
+        let injected = inject_base_path(index, "/a\"</script><script>alert(1)</script>");
+        assert!(!injected.contains("</script><script>alert"));
+        assert!(injected.contains("<base href=\"/a&quot;&lt;/script&gt;"));
+        assert!(injected.contains("__SLACKWATCH_BASE_PATH__ = \"/a\\\"\\u003c/script>"));
+    }
+
+    #[tokio::test]
+    async fn test_api_errors_map_to_status_codes() {
+        let route = warp::path("missing")
+            .and_then(|| async {
+                Err::<String, Rejection>(warp::reject::custom(ApiError::NotFound(
+                    "default/postgres".to_string(),
+                )))
+            })
+            .recover(handle_rejection);
+
+        let response = warp::test::request().path("/missing").reply(&route).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["error"], "default/postgres");
+
+        let response = warp::test::request().path("/unknown").reply(&route).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 }
@@ -0,0 +1,178 @@
+use crate::api::ApiError;
+use crate::auth::jwt::validate_token;
+use crate::config::{Auth, AuthMode};
+use crate::models::models::Role;
+use crate::secrets::resolve;
+use std::sync::Arc;
+use warp::http::HeaderMap;
+use warp::{Filter, Rejection};
+
+// The caller of an API request as established by the configured auth mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub subject: String,
+    pub role: Role,
+}
+
+/// Reject the request unless the caller holds at least `required`.
+pub fn require(
+    auth: Arc<Auth>,
+    required: Role,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::headers_cloned()
+        .and_then(move |headers: HeaderMap| {
+            let auth = auth.clone();
+            async move {
+                let identity = identify(&auth, &headers).map_err(warp::reject::custom)?;
+                if identity.role < required {
+                    log::warn!(
+                        "{} with role {} denied, {} required",
+                        identity.subject,
+                        identity.role,
+                        required
+                    );
+                    return Err(warp::reject::custom(ApiError::Forbidden(format!(
+                        "Role {} required",
+                        required
+                    ))));
+                }
+                Ok::<_, Rejection>(())
+            }
+        })
+        .untuple_one()
+}
+
+pub fn identify(auth: &Auth, headers: &HeaderMap) -> Result<Identity, ApiError> {
+    match auth.mode {
+        AuthMode::None => Ok(Identity {
+            subject: "anonymous".to_string(),
+            role: Role::Maintainer,
+        }),
+        AuthMode::Token => {
+            let token = bearer_token(headers)?;
+            auth.tokens
+                .iter()
+                .find(|t| resolve(&t.token()).is_some_and(|expected| constant_time_eq(expected.as_bytes(), token.as_bytes())))
+                .map(|t| Identity {
+                    subject: t.token().describe(),
+                    role: t.role,
+                })
+                .ok_or_else(|| ApiError::Unauthorized("Invalid token".to_string()))
+        }
+        AuthMode::Jwt => {
+            let jwt = auth
+                .jwt
+                .as_ref()
+                .ok_or_else(|| ApiError::Config("auth.jwt is not configured".to_string()))?;
+            let token = bearer_token(headers)?;
+            let (subject, role) = validate_token(jwt, token).map_err(ApiError::Unauthorized)?;
+            Ok(Identity { subject, role })
+        }
+        AuthMode::Proxy => {
+            let proxy = auth
+                .proxy
+                .as_ref()
+                .ok_or_else(|| ApiError::Config("auth.proxy is not configured".to_string()))?;
+            let subject = header_value(headers, &proxy.user_header)
+                .filter(|user| !user.is_empty())
+                .ok_or_else(|| {
+                    ApiError::Unauthorized(format!("Missing {} header", proxy.user_header))
+                })?;
+            let groups = header_value(headers, &proxy.groups_header).unwrap_or_default();
+            let role = if groups
+                .split(',')
+                .map(str::trim)
+                .any(|group| proxy.maintainer_groups.iter().any(|m| m == group))
+            {
+                Role::Maintainer
+            } else {
+                Role::Viewer
+            };
+            Ok(Identity {
+                subject: subject.to_string(),
+                role,
+            })
+        }
+    }
+}
+
+// Compares every byte whatever the first difference, so response times do not reveal how
+// much of a guessed token is right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+    for i in 0..a.len().max(b.len()) {
+        let (x, y) = (a.get(i).copied().unwrap_or(0), b.get(i).copied().unwrap_or(0));
+        diff |= usize::from(x ^ y);
+    }
+    std::hint::black_box(diff) == 0
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn bearer_token(headers: &HeaderMap) -> Result<&str, ApiError> {
+    header_value(headers, "authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .ok_or_else(|| ApiError::Unauthorized("Missing bearer token".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AuthToken, ProxyAuth};
+    use warp::http::HeaderValue;
+
+    #[test]
+    fn test_token_auth() {
+        std::env::set_var("SLACKWATCH_TEST_VIEWER_TOKEN", "viewer-secret");
+        let auth = Auth {
+            mode: AuthMode::Token,
+            tokens: vec![AuthToken {
+                token_env_name: Some("SLACKWATCH_TEST_VIEWER_TOKEN".to_string()),
+                token_file: None,
+                token_secret_ref: None,
+                role: Role::Viewer,
+            }],
+            ..Auth::default()
+        };
+        let mut headers = HeaderMap::new();
+        assert!(matches!(identify(&auth, &headers), Err(ApiError::Unauthorized(_))));
+
+        headers.insert("authorization", HeaderValue::from_static("Bearer wrong"));
+        assert!(matches!(identify(&auth, &headers), Err(ApiError::Unauthorized(_))));
+
+        headers.insert("authorization", HeaderValue::from_static("Bearer viewer-secre"));
+        assert!(matches!(identify(&auth, &headers), Err(ApiError::Unauthorized(_))));
+
+        headers.insert("authorization", HeaderValue::from_static("Bearer viewer-secret"));
+        assert_eq!(identify(&auth, &headers).unwrap().role, Role::Viewer);
+    }
+
+    #[test]
+    fn test_proxy_auth_roles() {
+        let auth = Auth {
+            mode: AuthMode::Proxy,
+            proxy: Some(ProxyAuth {
+                user_header: "X-Forwarded-User".to_string(),
+                groups_header: "X-Forwarded-Groups".to_string(),
+                maintainer_groups: vec!["platform".to_string()],
+            }),
+            ..Auth::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-user", HeaderValue::from_static("alice"));
+        headers.insert("x-forwarded-groups", HeaderValue::from_static("dev, platform"));
+        assert_eq!(
+            identify(&auth, &headers).unwrap(),
+            Identity {
+                subject: "alice".to_string(),
+                role: Role::Maintainer
+            }
+        );
+
+        headers.insert("x-forwarded-groups", HeaderValue::from_static("dev"));
+        assert_eq!(identify(&auth, &headers).unwrap().role, Role::Viewer);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::config::JwtAuth;
+use crate::models::models::Role;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// The parsed JWKS file with the path and modification time it was read at
+struct CachedJwks {
+    path: String,
+    modified: Option<SystemTime>,
+    jwks: Arc<JwkSet>,
+}
+
+static JWKS: Mutex<Option<CachedJwks>> = Mutex::new(None);
+
+// The keys of the JWKS file, read again only when the file changed
+fn load_jwks(path: &str) -> Result<Arc<JwkSet>, String> {
+    let modified = std::fs::metadata(path)
+        .map_err(|e| format!("Failed to read JWKS file {}: {}", path, e))?
+        .modified()
+        .ok();
+    let mut cached = JWKS.lock().unwrap();
+    if let Some(cached) = cached.as_ref().filter(|c| c.path == path && c.modified.is_some() && c.modified == modified) {
+        return Ok(cached.jwks.clone());
+    }
+    let jwks = std::fs::read_to_string(path).map_err(|e| format!("Failed to read JWKS file {}: {}", path, e))?;
+    let jwks: Arc<JwkSet> = Arc::new(serde_json::from_str(&jwks).map_err(|e| format!("Invalid JWKS file: {}", e))?);
+    log::info!("Loaded {} keys from JWKS file {}", jwks.keys.len(), path);
+    *cached = Some(CachedJwks {
+        path: path.to_string(),
+        modified,
+        jwks: jwks.clone(),
+    });
+    Ok(jwks)
+}
+
+/// The algorithms a key may verify: `algorithms` from the config when set, else the
+/// key's `alg`, else every algorithm of its key type. Never taken from the token.
+fn allowed_algorithms(config: &JwtAuth, jwk: &Jwk) -> Result<Vec<Algorithm>, String> {
+    if !config.algorithms.is_empty() {
+        return Ok(config.algorithms.clone());
+    }
+    if let Some(alg) = jwk.common.key_algorithm {
+        let alg = Algorithm::from_str(&alg.to_string()).map_err(|_| format!("Key algorithm {} cannot verify tokens", alg))?;
+        return Ok(vec![alg]);
+    }
+    Ok(match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => vec![Algorithm::ES256],
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => return Err(format!("Unsupported curve {:?}", params.curve)),
+        },
+        AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+        AlgorithmParameters::OctetKey(_) => vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512],
+    })
+}
+
+/// Validate a JWT against the keys in the configured JWKS file and return the
+/// subject together with the role derived from the roles claim.
+pub fn validate_token(config: &JwtAuth, token: &str) -> Result<(String, Role), String> {
+    let jwks = load_jwks(&config.jwks_file)?;
+
+    let header = decode_header(token).map_err(|e| format!("Invalid token header: {}", e))?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or_else(|| "No matching key found in JWKS".to_string())?;
+    let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("Invalid JWK: {}", e))?;
+
+    let algorithms = allowed_algorithms(config, jwk)?;
+    if !algorithms.contains(&header.alg) {
+        return Err(format!("Token algorithm {:?} is not allowed for this key", header.alg));
+    }
+    let mut validation = Validation::new(header.alg);
+    validation.algorithms = algorithms;
+    match &config.issuer {
+        Some(issuer) => validation.set_issuer(&[issuer]),
+        None => validation.iss = None,
+    }
+    match &config.audience {
+        Some(audience) => validation.set_audience(&[audience]),
+        None => validation.validate_aud = false,
+    }
+
+    let claims = decode::<HashMap<String, Value>>(token, &key, &validation)
+        .map_err(|e| format!("Invalid token: {}", e))?
+        .claims;
+    let subject = claims
+        .get("sub")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown")
+        .to_string();
+    let roles: Vec<&str> = match claims.get(&config.roles_claim) {
+        Some(Value::String(role)) => vec![role.as_str()],
+        Some(Value::Array(roles)) => roles.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let role = if roles.iter().any(|role| config.maintainer_roles.iter().any(|m| m == role)) {
+        Role::Maintainer
+    } else {
+        Role::Viewer
+    };
+    Ok((subject, role))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::io::Write;
+
+    fn config(jwks_file: &str) -> JwtAuth {
+        JwtAuth {
+            jwks_file: jwks_file.to_string(),
+            issuer: None,
+            audience: None,
+            roles_claim: "roles".to_string(),
+            maintainer_roles: vec!["admins".to_string()],
+            algorithms: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_algorithm_is_pinned_by_the_key() {
+        // "secret" base64url encoded
+        let jwks = r#"{"keys": [{"kty": "oct", "kid": "k1", "alg": "HS256", "k": "c2VjcmV0"}]}"#;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(jwks.as_bytes()).unwrap();
+        let config = config(file.path().to_str().unwrap());
+        let claims = serde_json::json!({ "sub": "alice", "roles": ["admins"], "exp": 4102444800u64 });
+        let token = |alg| {
+            let header = Header { kid: Some("k1".to_string()), ..Header::new(alg) };
+            encode(&header, &claims, &EncodingKey::from_secret(b"secret")).unwrap()
+        };
+
+        assert_eq!(validate_token(&config, &token(Algorithm::HS256)), Ok(("alice".to_string(), Role::Maintainer)));
+        assert!(validate_token(&config, &token(Algorithm::HS512)).unwrap_err().contains("not allowed"));
+        let config = JwtAuth { algorithms: vec![Algorithm::HS512], ..config };
+        assert!(validate_token(&config, &token(Algorithm::HS512)).is_ok());
+        assert!(validate_token(&config, &token(Algorithm::HS256)).is_err());
+    }
+}
@@ -1,15 +1,97 @@
 use config::{Config, ConfigError, Environment, File};
 use serde_derive::{Deserialize, Serialize};
+use ntfy::Priority;
+use crate::models::models::{Role, UpdateLevel, Workload};
+use cron::Schedule;
+use regex::Regex;
+use url::Url;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[allow(unused)]
 pub struct Settings {
     #[serde(default)]
     pub system: System,
     pub notifications: Option<Notifications>,
     pub gitops: Option<Vec<GitopsConfig>>,
+    #[serde(default)]
+    pub auth: Auth,
+    #[serde(default)]
+    pub registries: Vec<RegistryConfig>,
+    #[serde(default)]
+    pub mirrors: Vec<MirrorConfig>,
+    #[serde(default)]
+    pub schedules: Vec<ScheduleConfig>,
+    #[serde(default)]
+    pub auto_upgrade: AutoUpgrade,
+    /// Automatic upgrades only start while one of these is open, at any time when empty
+    #[serde(default)]
+    pub maintenance_windows: Vec<TimeWindow>,
+    #[serde(default)]
+    pub release_notes: ReleaseNotes,
+}
+
+/// Name of `system.schedule`, used by workloads without a `slackwatch.schedule` annotation
+pub const DEFAULT_SCHEDULE: &str = "default";
+
+// A named cron schedule, selected by workloads with the `slackwatch.schedule` annotation
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScheduleConfig {
+    pub name: String,
+    pub cron: String,
+}
+
+/// Limits on the upgrades run for workloads with a `slackwatch.auto_upgrade` policy after a
+/// scheduled scan. When they may start is set by `Settings::maintenance_windows`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AutoUpgrade {
+    #[serde(default = "default_max_per_run")]
+    pub max_per_run: usize,
+    /// Single window of earlier configs, read as one more of `Settings::maintenance_windows`
+    pub maintenance_window: Option<TimeWindow>,
+}
+
+impl Default for AutoUpgrade {
+    fn default() -> Self {
+        AutoUpgrade {
+            max_per_run: default_max_per_run(),
+            maintenance_window: None,
+        }
+    }
 }
 
+fn default_max_per_run() -> usize {
+    3
+}
+
+/// A recurring window, either `days` with a `start` and `end` time, or a `cron` expression
+/// for its start and a `duration`. Times are in `timezone`, UTC when unset.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TimeWindow {
+    /// Every day when empty
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub cron: Option<String>,
+    pub duration: Option<String>,
+    /// IANA name such as `Europe/Berlin`
+    pub timezone: Option<String>,
+}
+
+/// Notifications below `urgent_priority` are held while the window is open and sent when it ends.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct QuietHours {
+    #[serde(flatten)]
+    pub window: TimeWindow,
+    #[serde(default = "default_urgent_priority")]
+    pub urgent_priority: Priority,
+}
+
+fn default_urgent_priority() -> Priority {
+    Priority::Max
+}
 
 impl Default for System {
     fn default() -> Self {
@@ -17,6 +99,16 @@ impl Default for System {
             schedule: default_schedule(),
             data_dir: default_data_dir(),
             run_at_startup: default_run_at_startup(),
+            listen_address: default_listen_address(),
+            port: default_port(),
+            tls_cert: None,
+            tls_key: None,
+            base_path: String::new(),
+            static_dir: default_static_dir(),
+            scan_concurrency: default_scan_concurrency(),
+            tag_cache_ttl_seconds: default_tag_cache_ttl_seconds(),
+            min_age: None,
+            rollout_timeout: default_rollout_timeout(),
         }
     }
 }
@@ -30,6 +122,33 @@ pub struct System {
     pub data_dir: String,
     #[serde(default = "default_run_at_startup")]
     pub run_at_startup: bool,
+    #[serde(default = "default_listen_address")]
+    pub listen_address: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// TLS is enabled when both a certificate and key path are set
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// URL prefix when served from an ingress sub-path, e.g. `/slackwatch`
+    #[serde(default)]
+    pub base_path: String,
+    #[serde(default = "default_static_dir")]
+    pub static_dir: String,
+    /// Number of image repositories fetched at the same time during a scan
+    #[serde(default = "default_scan_concurrency")]
+    pub scan_concurrency: usize,
+    /// How long a fetched tag list is reused before asking the registry again
+    #[serde(default = "default_tag_cache_ttl_seconds")]
+    pub tag_cache_ttl_seconds: u64,
+    /// Default minimum age of a release before it is reported, e.g. `3d`
+    pub min_age: Option<String>,
+    /// How long to wait for an upgrade to roll out before reporting it failed, `0` to not wait
+    #[serde(default = "default_rollout_timeout")]
+    pub rollout_timeout: String,
+}
+
+fn default_rollout_timeout() -> String {
+    "15m".to_string()
 }
 
 fn default_schedule() -> String {
@@ -43,6 +162,155 @@ fn default_run_at_startup() -> bool {
     false
 }
 
+fn default_listen_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_static_dir() -> String {
+    "frontend/dist".to_string()
+}
+
+fn default_scan_concurrency() -> usize {
+    4
+}
+
+fn default_tag_cache_ttl_seconds() -> u64 {
+    3600
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorQuery {
+    #[default]
+    Upstream,
+    Mirror,
+}
+
+// Maps images pulled through a mirror or pull-through cache back to their upstream registry
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MirrorConfig {
+    /// Image prefix of the mirror, e.g. `harbor.local/dockerhub`
+    pub prefix: String,
+    /// Registry, optionally with a path, the mirror proxies, e.g. `docker.io`
+    pub upstream: String,
+    /// Whether tags are listed from the upstream registry or the mirror itself
+    #[serde(default)]
+    pub query: MirrorQuery,
+}
+
+// Per registry settings, matched on the registry host of an image reference
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RegistryConfig {
+    pub host: String,
+    pub requests_per_minute: Option<u32>,
+    /// Overrides `system.tag_cache_ttl_seconds` for this registry
+    pub cache_ttl_seconds: Option<u64>,
+    /// Environment variable holding a token for the registry's own API, needed for ghcr.io
+    pub api_token_env_name: Option<String>,
+    pub api_token_file: Option<String>,
+    pub api_token_secret_ref: Option<SecretRef>,
+    /// Pull credentials for tags, manifests and image configs, anonymous when unset
+    pub username: Option<String>,
+    pub password_env_name: Option<String>,
+    pub password_file: Option<String>,
+    pub password_secret_ref: Option<SecretRef>,
+}
+
+impl RegistryConfig {
+    pub fn api_token(&self) -> SecretSource {
+        SecretSource {
+            env_name: self.api_token_env_name.clone(),
+            file: self.api_token_file.clone(),
+            secret_ref: self.api_token_secret_ref.clone(),
+            ..SecretSource::default()
+        }
+    }
+
+    pub fn password(&self) -> SecretSource {
+        SecretSource {
+            env_name: self.password_env_name.clone(),
+            file: self.password_file.clone(),
+            secret_ref: self.password_secret_ref.clone(),
+            ..SecretSource::default()
+        }
+    }
+}
+
+/// Lookup of release notes on the forge hosting an image's source
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ReleaseNotes {
+    /// Environment variable holding a GitHub token, raising the API limit from 60 requests an hour
+    pub github_token_env_name: Option<String>,
+    pub github_token_file: Option<String>,
+    pub github_token_secret_ref: Option<SecretRef>,
+}
+
+impl ReleaseNotes {
+    pub fn github_token(&self) -> SecretSource {
+        SecretSource {
+            env_name: self.github_token_env_name.clone(),
+            file: self.github_token_file.clone(),
+            secret_ref: self.github_token_secret_ref.clone(),
+            ..SecretSource::default()
+        }
+    }
+}
+
+/// A key of a Kubernetes Secret holding a secret setting
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SecretRef {
+    pub namespace: String,
+    pub name: String,
+    pub key: String,
+}
+
+/// A secret written into the config file. Shown as `[redacted]` in logs and the API.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct Sensitive(pub String);
+
+impl std::fmt::Debug for Sensitive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+impl serde::Serialize for Sensitive {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("[redacted]")
+    }
+}
+
+/// Where a secret setting is read from. The first source set is used, see `crate::secrets`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SecretSource {
+    pub value: Option<Sensitive>,
+    pub env_name: Option<String>,
+    pub file: Option<String>,
+    pub secret_ref: Option<SecretRef>,
+}
+
+impl SecretSource {
+    pub fn is_set(&self) -> bool {
+        self.value.is_some() || self.env_name.is_some() || self.file.is_some() || self.secret_ref.is_some()
+    }
+
+    /// Where the secret comes from, without its value
+    pub fn describe(&self) -> String {
+        match (&self.env_name, &self.file, &self.secret_ref) {
+            _ if self.value.is_some() => "config file".to_string(),
+            (Some(name), _, _) => name.clone(),
+            (None, Some(path), _) => path.clone(),
+            (None, None, Some(secret_ref)) => format!("{}/{}/{}", secret_ref.namespace, secret_ref.name, secret_ref.key),
+            (None, None, None) => "unset".to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[allow(unused)]
 pub struct GitopsConfig {
@@ -51,14 +319,177 @@ pub struct GitopsConfig {
     pub branch: String,
     pub commit_name: String,
     pub commit_email: String,
-    pub access_token_env_name: String,
+    pub access_token_env_name: Option<String>,
+    pub access_token_file: Option<String>,
+    pub access_token_secret_ref: Option<SecretRef>,
     pub commit_message: String,
 }
 
+impl GitopsConfig {
+    pub fn access_token(&self) -> SecretSource {
+        SecretSource {
+            env_name: self.access_token_env_name.clone(),
+            file: self.access_token_file.clone(),
+            secret_ref: self.access_token_secret_ref.clone(),
+            ..SecretSource::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    #[default]
+    None,
+    Token,
+    Jwt,
+    Proxy,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[allow(unused)]
+pub struct Auth {
+    #[serde(default)]
+    pub mode: AuthMode,
+    /// Origins allowed by CORS, any origin is allowed when empty
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    pub tokens: Vec<AuthToken>,
+    pub jwt: Option<JwtAuth>,
+    pub proxy: Option<ProxyAuth>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[allow(unused)]
+pub struct AuthToken {
+    pub token_env_name: Option<String>,
+    pub token_file: Option<String>,
+    pub token_secret_ref: Option<SecretRef>,
+    pub role: Role,
+}
+
+impl AuthToken {
+    pub fn token(&self) -> SecretSource {
+        SecretSource {
+            env_name: self.token_env_name.clone(),
+            file: self.token_file.clone(),
+            secret_ref: self.token_secret_ref.clone(),
+            ..SecretSource::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[allow(unused)]
+pub struct JwtAuth {
+    pub jwks_file: String,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    #[serde(default = "default_roles_claim")]
+    pub roles_claim: String,
+    #[serde(default)]
+    pub maintainer_roles: Vec<String>,
+    /// Algorithms tokens may be signed with, from each key's `alg` or type when empty
+    #[serde(default)]
+    pub algorithms: Vec<jsonwebtoken::Algorithm>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[allow(unused)]
+pub struct ProxyAuth {
+    #[serde(default = "default_user_header")]
+    pub user_header: String,
+    #[serde(default = "default_groups_header")]
+    pub groups_header: String,
+    #[serde(default)]
+    pub maintainer_groups: Vec<String>,
+}
+
+fn default_roles_claim() -> String {
+    "roles".to_string()
+}
+
+fn default_user_header() -> String {
+    "X-Forwarded-User".to_string()
+}
+
+fn default_groups_header() -> String {
+    "X-Forwarded-Groups".to_string()
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[allow(unused)]
 pub struct Notifications {
     pub ntfy: Option<Ntfy>,
+    #[serde(default)]
+    pub rules: Vec<NotificationRule>,
+    #[serde(default)]
+    pub failures: FailureAlerts,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+/// Alerting for scan and gitops failures. A threshold is the number of consecutive
+/// failures for the same image or repository before a notification is sent.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[allow(unused)]
+pub struct FailureAlerts {
+    #[serde(default = "default_scan_threshold")]
+    pub scan_threshold: u32,
+    #[serde(default = "default_failure_threshold")]
+    pub auth_threshold: u32,
+    #[serde(default = "default_failure_threshold")]
+    pub gitops_threshold: u32,
+    #[serde(default = "default_failure_threshold")]
+    pub annotation_threshold: u32,
+    pub topic: Option<String>,
+    pub priority: Option<Priority>,
+}
+
+impl Default for FailureAlerts {
+    fn default() -> Self {
+        FailureAlerts {
+            scan_threshold: default_scan_threshold(),
+            auth_threshold: default_failure_threshold(),
+            gitops_threshold: default_failure_threshold(),
+            annotation_threshold: default_failure_threshold(),
+            topic: None,
+            priority: None,
+        }
+    }
+}
+
+fn default_scan_threshold() -> u32 {
+    3
+}
+
+fn default_failure_threshold() -> u32 {
+    1
+}
+
+/// Routing rule for update notifications. Every match field that is set must
+/// match the workload; the first matching rule decides where the notification goes.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[allow(unused)]
+pub struct NotificationRule {
+    /// Glob matched against the workload namespace, e.g. `prod-*`
+    pub namespace: Option<String>,
+    /// Glob matched against the workload name
+    pub name: Option<String>,
+    /// Name of the gitops configuration referenced by `slackwatch.repo`
+    pub git_ops_repo: Option<String>,
+    /// One of `major`, `minor` or `patch`
+    pub update_level: Option<UpdateLevel>,
+    #[serde(default = "default_notifier")]
+    pub notifier: String,
+    pub topic: Option<String>,
+    pub priority: Option<Priority>,
+    #[serde(default)]
+    pub silence: bool,
+}
+
+fn default_notifier() -> String {
+    "ntfy".to_string()
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -67,7 +498,45 @@ pub struct Ntfy {
     pub url: String,
     pub topic: String,
     pub reminder: String,
-    pub token: String,
+    pub token: Option<Sensitive>,
+    pub token_file: Option<String>,
+    pub token_secret_ref: Option<SecretRef>,
+}
+
+impl Ntfy {
+    pub fn token(&self) -> SecretSource {
+        SecretSource {
+            value: self.token.clone(),
+            file: self.token_file.clone(),
+            secret_ref: self.token_secret_ref.clone(),
+            ..SecretSource::default()
+        }
+    }
+}
+
+// Longest duration accepted, a century
+const MAX_DURATION_DAYS: i64 = 36500;
+
+/// Parse a duration such as `90s`, `30m`, `12h`, `3d` or `2w`. A bare number is seconds.
+pub fn parse_duration(value: &str) -> Result<chrono::Duration, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid duration {:?}", value))?;
+    let duration = match unit {
+        "" | "s" => chrono::Duration::try_seconds(amount),
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        "d" => chrono::Duration::try_days(amount),
+        "w" => chrono::Duration::try_weeks(amount),
+        _ => return Err(format!("Invalid duration unit {:?} in {:?}", unit, value)),
+    };
+    // Durations are added to and taken from dates, which overflow long before `Duration` does
+    duration
+        .filter(|duration| *duration <= chrono::Duration::days(MAX_DURATION_DAYS))
+        .ok_or_else(|| format!("Invalid duration {:?}, longer than {} days", value, MAX_DURATION_DAYS))
 }
 
 impl Settings {
@@ -82,11 +551,228 @@ impl Settings {
             .add_source(File::with_name(&env_config).required(false))
             .add_source(Environment::with_prefix("slackwatch"))
             .build()?;
-        //print config
-        println!("{:?}", s);
-        s.try_deserialize::<Settings>().or_else(|e| Err(e))
+        s.try_deserialize::<Settings>()
     }
     //add clone
+
+    /// Check the settings before they are applied, returning every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if let Err(e) = Schedule::from_str(&self.system.schedule) {
+            errors.push(format!("system.schedule: invalid cron expression {:?}: {}", self.system.schedule, e));
+        }
+        if let Some(min_age) = &self.system.min_age {
+            if let Err(e) = parse_duration(min_age) {
+                errors.push(format!("system.min_age: {}", e));
+            }
+        }
+        if let Err(e) = self.system.listen_address.parse::<std::net::IpAddr>() {
+            errors.push(format!("system.listen_address: invalid IP address {:?}: {}", self.system.listen_address, e));
+        }
+        match (&self.system.tls_cert, &self.system.tls_key) {
+            (Some(_), None) => errors.push("system.tls_key: must be set together with tls_cert".to_string()),
+            (None, Some(_)) => errors.push("system.tls_cert: must be set together with tls_key".to_string()),
+            _ => {}
+        }
+        if let Err(e) = parse_duration(&self.system.rollout_timeout) {
+            errors.push(format!("system.rollout_timeout: {}", e));
+        }
+        for (i, window) in self.maintenance_windows.iter().enumerate() {
+            if let Err(e) = window.check() {
+                errors.push(format!("maintenance_windows[{}]: {}", i, e));
+            }
+        }
+        if let Some(window) = &self.auto_upgrade.maintenance_window {
+            if let Err(e) = window.check() {
+                errors.push(format!("auto_upgrade.maintenance_window: {}", e));
+            }
+        }
+        if let Some(quiet_hours) = self.notifications.as_ref().and_then(|n| n.quiet_hours.as_ref()) {
+            if let Err(e) = quiet_hours.window.check() {
+                errors.push(format!("notifications.quiet_hours: {}", e));
+            }
+        }
+        for (i, schedule) in self.schedules.iter().enumerate() {
+            if let Err(e) = Schedule::from_str(&schedule.cron) {
+                errors.push(format!("schedules[{}].cron: invalid cron expression {:?}: {}", i, schedule.cron, e));
+            }
+            if schedule.name == DEFAULT_SCHEDULE || self.schedules[..i].iter().any(|other| other.name == schedule.name) {
+                errors.push(format!("schedules[{}].name: {:?} is already used", i, schedule.name));
+            }
+        }
+
+        let gitops = self.gitops.as_deref().unwrap_or_default();
+        for (i, repo) in gitops.iter().enumerate() {
+            check_url(&mut errors, &format!("gitops[{}].repository_url", i), &repo.repository_url);
+            check_secret(&mut errors, &format!("gitops[{}].access_token", i), &repo.access_token(), true);
+            if gitops[..i].iter().any(|other| other.name == repo.name) {
+                errors.push(format!("gitops[{}].name: {:?} is used by more than one repository", i, repo.name));
+            }
+        }
+
+        if let Some(notifications) = &self.notifications {
+            if let Some(ntfy) = &notifications.ntfy {
+                check_url(&mut errors, "notifications.ntfy.url", &ntfy.url);
+                check_secret(&mut errors, "notifications.ntfy.token", &ntfy.token(), false);
+            }
+            for (i, rule) in notifications.rules.iter().enumerate() {
+                if let Some(name) = &rule.git_ops_repo {
+                    if !self.has_gitops_repo(name) {
+                        errors.push(format!("notifications.rules[{}].git_ops_repo: no gitops repository named {:?}", i, name));
+                    }
+                }
+            }
+        }
+
+        for (i, token) in self.auth.tokens.iter().enumerate() {
+            check_secret(&mut errors, &format!("auth.tokens[{}].token", i), &token.token(), true);
+        }
+        for (i, registry) in self.registries.iter().enumerate() {
+            check_secret(&mut errors, &format!("registries[{}].api_token", i), &registry.api_token(), false);
+            let password = registry.password();
+            check_secret(&mut errors, &format!("registries[{}].password", i), &password, registry.username.is_some());
+            if password.is_set() && registry.username.is_none() {
+                errors.push(format!("registries[{}].username: required with a password", i));
+            }
+        }
+        check_secret(&mut errors, "release_notes.github_token", &self.release_notes.github_token(), false);
+        for (i, mirror) in self.mirrors.iter().enumerate() {
+            if mirror.prefix.trim_matches('/').is_empty() || mirror.upstream.trim_matches('/').is_empty() {
+                errors.push(format!("mirrors[{}]: prefix and upstream must not be empty", i));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Every secret setting, used to fetch the Kubernetes Secrets they reference
+    pub fn secret_sources(&self) -> Vec<SecretSource> {
+        let ntfy = self.notifications.iter().flat_map(|notifications| &notifications.ntfy);
+        ntfy.map(Ntfy::token)
+            .chain(self.gitops.iter().flatten().map(GitopsConfig::access_token))
+            .chain(self.auth.tokens.iter().map(AuthToken::token))
+            .chain(self.registries.iter().map(RegistryConfig::api_token))
+            .chain(self.registries.iter().map(RegistryConfig::password))
+            .chain(std::iter::once(self.release_notes.github_token()))
+            .collect()
+    }
+
+    /// Every schedule by name with its cron expression, `system.schedule` first as `default`
+    pub fn all_schedules(&self) -> Vec<(&str, &str)> {
+        std::iter::once((DEFAULT_SCHEDULE, self.system.schedule.as_str()))
+            .chain(self.schedules.iter().map(|s| (s.name.as_str(), s.cron.as_str())))
+            .collect()
+    }
+
+    /// Every maintenance window, with `auto_upgrade.maintenance_window` of earlier configs last.
+    pub fn all_maintenance_windows(&self) -> impl Iterator<Item = &TimeWindow> {
+        self.maintenance_windows.iter().chain(&self.auto_upgrade.maintenance_window)
+    }
+
+    /// The schedule a workload is scanned on. Unknown names fall back to the default schedule.
+    pub fn schedule_of<'a>(&'a self, workload: &'a Workload) -> &'a str {
+        match &workload.schedule {
+            Some(name) if self.schedules.iter().any(|s| &s.name == name) => name,
+            _ => DEFAULT_SCHEDULE,
+        }
+    }
+
+    fn has_gitops_repo(&self, name: &str) -> bool {
+        self.gitops.iter().flatten().any(|repo| repo.name == name)
+    }
+
+    /// Check the annotations of a workload against these settings, returning every problem found.
+    pub fn validate_workload(&self, workload: &Workload) -> Vec<String> {
+        let subject = format!("{}/{}", workload.namespace, workload.name);
+        let mut errors = Vec::new();
+        for (annotation, patterns) in [
+            ("slackwatch.include", &workload.include_pattern),
+            ("slackwatch.exclude", &workload.exclude_pattern),
+        ] {
+            for pattern in patterns.iter().flat_map(|patterns| patterns.split(',')) {
+                if let Err(e) = Regex::new(pattern) {
+                    errors.push(format!("{} {}: invalid pattern {:?}: {}", subject, annotation, pattern, e));
+                }
+            }
+        }
+        if let Some(min_age) = &workload.min_age {
+            if let Err(e) = parse_duration(min_age) {
+                errors.push(format!("{} slackwatch.min_age: {}", subject, e));
+            }
+        }
+        if let Some(name) = &workload.git_ops_repo {
+            if !self.has_gitops_repo(name) {
+                errors.push(format!("{} slackwatch.repo: no gitops repository named {:?}", subject, name));
+            }
+        }
+        if let Some(policy) = &workload.auto_upgrade {
+            if UpdateLevel::from_str(policy).is_err() {
+                errors.push(format!("{} slackwatch.auto_upgrade: expected patch, minor or major, got {:?}", subject, policy));
+            } else if workload.git_ops_repo.is_none() {
+                errors.push(format!("{} slackwatch.auto_upgrade: needs a slackwatch.repo to commit to", subject));
+            }
+        }
+        if let Some(name) = &workload.schedule {
+            if name != DEFAULT_SCHEDULE && self.schedule_of(workload) == DEFAULT_SCHEDULE {
+                errors.push(format!("{} slackwatch.schedule: no schedule named {:?}, using the default", subject, name));
+            }
+        }
+        errors
+    }
+}
+
+fn check_url(errors: &mut Vec<String>, field: &str, value: &str) {
+    if let Err(e) = Url::parse(value) {
+        errors.push(format!("{}: invalid URL {:?}: {}", field, value, e));
+    }
+}
+
+// Secrets held in a Kubernetes Secret are checked when they are read, see `crate::secrets`
+fn check_secret(errors: &mut Vec<String>, field: &str, source: &SecretSource, required: bool) {
+    if required && !source.is_set() {
+        errors.push(format!(
+            "{}: one of {0}_env_name, {0}_file or {0}_secret_ref is required",
+            field
+        ));
+    }
+    if let Some(name) = &source.env_name {
+        if std::env::var(name).map_or(true, |value| value.is_empty()) {
+            errors.push(format!("{}_env_name: environment variable {} is not set", field, name));
+        }
+    }
+    if let Some(path) = &source.file {
+        if let Err(e) = std::fs::metadata(path) {
+            errors.push(format!("{}_file: cannot read {}: {}", field, path, e));
+        }
+    }
+}
+
+static CURRENT: RwLock<Option<Arc<Settings>>> = RwLock::new(None);
+
+/// The settings in effect, shared by every module. Loaded on first use and replaced
+/// when the config file is reloaded.
+pub fn current_settings() -> Arc<Settings> {
+    if let Some(settings) = CURRENT.read().unwrap().as_ref() {
+        return settings.clone();
+    }
+    let settings = Settings::new().unwrap_or_else(|e| {
+        log::error!("Failed to load settings, using defaults: {}", e);
+        Settings::default()
+    });
+    CURRENT
+        .write()
+        .unwrap()
+        .get_or_insert_with(|| Arc::new(settings))
+        .clone()
+}
+
+/// Make `settings` the settings in effect, returning the ones they replace.
+pub fn replace_settings(settings: Settings) -> Option<Arc<Settings>> {
+    CURRENT.write().unwrap().replace(Arc::new(settings))
 }
 
 #[cfg(test)]
@@ -170,4 +856,91 @@ mod tests {
             "Environment variable should override file configuration"
         );
     }
+
+    #[test]
+    fn test_validate_rejects_invalid_schedule() {
+        let mut settings = Settings::default();
+        assert!(settings.validate().is_ok());
+        settings.system.schedule = "every hour".to_string();
+        let errors = settings.validate().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("system.schedule"));
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let gitops = GitopsConfig {
+            name: "homelab".to_string(),
+            repository_url: "not a url".to_string(),
+            branch: "main".to_string(),
+            commit_name: "slackwatch".to_string(),
+            commit_email: "slackwatch@example.com".to_string(),
+            access_token_env_name: Some("SLACKWATCH_TEST_UNSET_TOKEN".to_string()),
+            access_token_file: None,
+            access_token_secret_ref: None,
+            commit_message: "Update".to_string(),
+        };
+        let mut settings = Settings {
+            gitops: Some(vec![gitops.clone(), gitops]),
+            ..Settings::default()
+        };
+        settings.system.min_age = Some("3 days".to_string());
+        settings.system.listen_address = "localhost".to_string();
+        settings.system.tls_cert = Some("/app/tls/tls.crt".to_string());
+        let errors = settings.validate().unwrap_err();
+        let fields: Vec<&str> = errors.iter().filter_map(|e| e.split(':').next()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "system.min_age",
+                "system.listen_address",
+                "system.tls_key",
+                "gitops[0].repository_url",
+                "gitops[0].access_token_env_name",
+                "gitops[1].repository_url",
+                "gitops[1].access_token_env_name",
+                "gitops[1].name",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_workload() {
+        let workload = Workload {
+            exclude_pattern: Some("-alpine$,(rc".to_string()),
+            git_ops_repo: Some("homelab".to_string()),
+            min_age: Some("2w".to_string()),
+            ..Workload::for_test("postgres", "postgres:16.1.0", "16.1.0", "")
+        };
+        let errors = Settings::default().validate_workload(&workload);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("default/postgres slackwatch.exclude: invalid pattern \"(rc\""));
+        assert!(errors[1].starts_with("default/postgres slackwatch.repo"));
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let ntfy = Ntfy {
+            url: "http://ntfy.example.com".to_string(),
+            topic: "updates".to_string(),
+            reminder: "24h".to_string(),
+            token: Some(Sensitive("secrettoken".to_string())),
+            token_file: None,
+            token_secret_ref: None,
+        };
+        assert!(!format!("{:?}", ntfy).contains("secrettoken"));
+        assert_eq!(serde_json::to_value(&ntfy).unwrap()["token"], "[redacted]");
+        assert_eq!(crate::secrets::resolve(&ntfy.token()).as_deref(), Some("secrettoken"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("3d"), Ok(chrono::Duration::days(3)));
+        assert_eq!(parse_duration("12h"), Ok(chrono::Duration::hours(12)));
+        assert_eq!(parse_duration("90"), Ok(chrono::Duration::seconds(90)));
+        assert!(parse_duration("3 days").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("99999999999999w").is_err());
+        assert!(parse_duration("9223372036854775807s").is_err());
+    }
 }
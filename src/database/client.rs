@@ -1,7 +1,9 @@
-use crate::models::models::UpdateStatus;
+use crate::models::models::{
+    CachedTags, FailureKind, Ignore, ImageMetadata, ImageTag, Job, JobState, JobType, ReleaseNote, UpdateStatus,
+};
 use crate::models::models::Workload;
 use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
-use rusqlite::{Connection, Error, Result, ToSql};
+use rusqlite::{Connection, Error, OptionalExtension, Result, ToSql};
 
 pub fn create_table_if_not_exist() -> Result<()> {
     let conn = Connection::open("data.db")?;
@@ -24,13 +26,134 @@ pub fn create_table_if_not_exist() -> Result<()> {
                   )",
         [],
     )?;
+    add_column_if_missing(&conn, "workloads", "source", "TEXT")?;
+    add_column_if_missing(&conn, "workloads", "changelog_url", "TEXT")?;
+    add_column_if_missing(&conn, "workloads", "current_metadata", "TEXT")?;
+    add_column_if_missing(&conn, "workloads", "latest_metadata", "TEXT")?;
+    add_column_if_missing(&conn, "workloads", "schedule", "TEXT")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS failures (
+                  kind            TEXT NOT NULL,
+                  subject         TEXT NOT NULL,
+                  count           INTEGER NOT NULL,
+                  last_error      TEXT NOT NULL,
+                  last_failed     TEXT NOT NULL,
+                  PRIMARY KEY (kind, subject)
+                  )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS jobs (
+                  id              INTEGER PRIMARY KEY,
+                  job_type        TEXT NOT NULL,
+                  state           TEXT NOT NULL,
+                  target          TEXT,
+                  created_at      TEXT NOT NULL,
+                  finished_at     TEXT,
+                  result          TEXT,
+                  error           TEXT,
+                  log             TEXT
+                  )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tag_cache (
+                  repository      TEXT PRIMARY KEY,
+                  tags            TEXT NOT NULL,
+                  etag            TEXT,
+                  fetched_at      TEXT NOT NULL
+                  )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS config_cache (
+                  repository      TEXT NOT NULL,
+                  tag             TEXT NOT NULL,
+                  config          TEXT NOT NULL,
+                  fetched_at      TEXT NOT NULL,
+                  PRIMARY KEY (repository, tag)
+                  )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS ignores (
+                  id              INTEGER PRIMARY KEY,
+                  namespace       TEXT NOT NULL,
+                  name            TEXT NOT NULL,
+                  version         TEXT,
+                  version_range   TEXT,
+                  until           TEXT,
+                  created_at      TEXT NOT NULL
+                  )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS release_notes (
+                  source          TEXT NOT NULL,
+                  version         TEXT NOT NULL,
+                  title           TEXT,
+                  url             TEXT NOT NULL,
+                  body            TEXT,
+                  published_at    TEXT,
+                  PRIMARY KEY (source, version)
+                  )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS release_note_misses (
+                  source          TEXT NOT NULL,
+                  version         TEXT NOT NULL,
+                  checked_at      TEXT NOT NULL,
+                  PRIMARY KEY (source, version)
+                  )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS auto_upgrades (
+                  namespace       TEXT NOT NULL,
+                  name            TEXT NOT NULL,
+                  version         TEXT NOT NULL,
+                  job_id          INTEGER NOT NULL,
+                  started_at      TEXT NOT NULL,
+                  PRIMARY KEY (namespace, name, version)
+                  )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS deferred_notifications (
+                  id              INTEGER PRIMARY KEY,
+                  payload         TEXT NOT NULL,
+                  send_after      TEXT NOT NULL,
+                  attempts        INTEGER NOT NULL DEFAULT 0
+                  )",
+        [],
+    )?;
+    add_column_if_missing(&conn, "deferred_notifications", "attempts", "INTEGER NOT NULL DEFAULT 0")?;
     Ok(())
 }
 
+// Columns added after the first release, tables created by older versions lack them
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>>>()?;
+    if !columns.iter().any(|name| name == column) {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
+
+/// Check that the database can take a write lock, used by the readiness probe.
+pub fn check_writable() -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;")
+}
+
 pub fn return_workload(name: String, namespace: String) -> Result<Workload> {
     let conn = Connection::open("data.db")?;
     let mut stmt = conn.prepare("SELECT * FROM workloads WHERE name = ?1 AND namespace = ?2")?;
-    let mut workload = stmt.query_map(&[&name, &namespace], |row| {
+    let mut workload = stmt.query_map([&name, &namespace], |row| {
         Ok(Workload {
             name: row.get(1)?,
             image: row.get(2)?,
@@ -43,13 +166,21 @@ pub fn return_workload(name: String, namespace: String) -> Result<Workload> {
             latest_version: row.get(9)?,
             last_scanned: row.get(10)?,
             git_directory: row.get(13)?,
+            min_age: None,
+            arch: None,
+            source: row.get(14)?,
+            changelog_url: row.get(15)?,
+            current_metadata: metadata_from_column(row.get(16)?),
+            latest_metadata: metadata_from_column(row.get(17)?),
+            schedule: row.get(18)?,
+            auto_upgrade: None,
         })
     })?;
     if let Some(workload) = workload.next() {
-        return Ok(workload?);
+        workload
     } else {
-        return Err(rusqlite::Error::QueryReturnedNoRows);
-    };
+        Err(rusqlite::Error::QueryReturnedNoRows)
+    }
 }
 
 pub fn return_all_workloads() -> Result<Vec<Workload>> {
@@ -95,6 +226,14 @@ JOIN MaxLastScanned mls ON f.name = mls.name AND f.namespace = mls.namespace AND
             latest_version: row.get(9)?,
             last_scanned: row.get(10)?,
             git_directory: row.get(13)?,
+            min_age: None,
+            arch: None,
+            source: row.get(14)?,
+            changelog_url: row.get(15)?,
+            current_metadata: metadata_from_column(row.get(16)?),
+            latest_metadata: metadata_from_column(row.get(17)?),
+            schedule: row.get(18)?,
+            auto_upgrade: None,
         })
     })?;
     let mut result = Vec::new();
@@ -119,6 +258,15 @@ impl FromSql for UpdateStatus {
     }
 }
 
+// Image metadata is stored as JSON, unreadable values are dropped
+fn metadata_to_column(metadata: &Option<ImageMetadata>) -> Option<String> {
+    metadata.as_ref().and_then(|metadata| serde_json::to_string(metadata).ok())
+}
+
+fn metadata_from_column(value: Option<String>) -> Option<ImageMetadata> {
+    value.and_then(|value| serde_json::from_str(&value).ok())
+}
+
 pub fn get_latest_scan_id() -> std::result::Result<i32, Error> {
     let conn = Connection::open("data.db")?;
     let mut stmt = conn.prepare("SELECT MAX(scan_id) FROM workloads")?;
@@ -134,25 +282,403 @@ pub fn insert_workload(workload: &Workload, scan_id: i32) -> Result<()> {
     let conn = Connection::open("data.db")?;
     //get scan_id
     match conn.execute(
-        "INSERT INTO workloads (name, image, namespace, git_ops_repo, include_pattern, exclude_pattern, update_available, current_version, latest_version, last_scanned, scan_id, scan_type, git_directory)
-                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
-        [
+        "INSERT INTO workloads (name, image, namespace, git_ops_repo, include_pattern, exclude_pattern, update_available, current_version, latest_version, last_scanned, scan_id, scan_type, git_directory, source, changelog_url, current_metadata, latest_metadata, schedule)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+        rusqlite::params![
             &workload.name,
             &workload.image,
             &workload.namespace,
-            workload.git_ops_repo.as_ref().map(String::as_str).unwrap_or_default(), // Handle potential None
-            workload.include_pattern.as_ref().map(String::as_str).unwrap_or_default(),
-            workload.exclude_pattern.as_ref().map(String::as_str).unwrap_or_default(),
+            workload.git_ops_repo.as_deref().unwrap_or_default(), // Handle potential None
+            workload.include_pattern.as_deref().unwrap_or_default(),
+            workload.exclude_pattern.as_deref().unwrap_or_default(),
             &workload.update_available.to_string(), // Consider an enum for clarity
             &workload.current_version,
             &workload.latest_version,
             &workload.last_scanned,
             &scan_id.to_string(),
             &workload.name,
-            &workload.git_directory.as_ref().map(String::as_str).unwrap_or_default(),
+            workload.git_directory.as_deref().unwrap_or_default(),
+            workload.source,
+            workload.changelog_url,
+            metadata_to_column(&workload.current_metadata),
+            metadata_to_column(&workload.latest_metadata),
+            workload.schedule,
         ],
     ) {
         Ok(_) => Ok(()),
         Err(e) => Err(e),
     }
 }
+
+/// Increment the consecutive failure count for `subject` and return the new count.
+pub fn record_failure(kind: &FailureKind, subject: &str, error: &str) -> Result<u32> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "INSERT INTO failures (kind, subject, count, last_error, last_failed)
+                  VALUES (?1, ?2, 1, ?3, ?4)
+                  ON CONFLICT(kind, subject) DO UPDATE SET
+                  count = count + 1, last_error = excluded.last_error, last_failed = excluded.last_failed",
+        [kind.to_string().as_str(), subject, error, &chrono::Utc::now().to_rfc3339()],
+    )?;
+    conn.query_row(
+        "SELECT count FROM failures WHERE kind = ?1 AND subject = ?2",
+        [kind.to_string().as_str(), subject],
+        |row| row.get(0),
+    )
+}
+
+/// Reset the failure count for `subject`, returning the count it had before. Nothing is
+/// written when no failure is recorded.
+pub fn clear_failures(kind: &FailureKind, subject: &str) -> Result<u32> {
+    let conn = Connection::open("data.db")?;
+    let count = conn
+        .query_row(
+            "SELECT count FROM failures WHERE kind = ?1 AND subject = ?2",
+            [kind.to_string().as_str(), subject],
+            |row| row.get(0),
+        )
+        .or_else(|e| match e {
+            Error::QueryReturnedNoRows => Ok(0),
+            e => Err(e),
+        })?;
+    if count > 0 {
+        conn.execute(
+            "DELETE FROM failures WHERE kind = ?1 AND subject = ?2",
+            [kind.to_string().as_str(), subject],
+        )?;
+    }
+    Ok(count)
+}
+
+pub fn insert_job(job_type: JobType, target: Option<&str>) -> Result<i64> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "INSERT INTO jobs (job_type, state, target, created_at) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![
+            job_type.to_string(),
+            JobState::Running.to_string(),
+            target,
+            chrono::Utc::now().to_rfc3339()
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn finish_job(
+    id: i64,
+    state: JobState,
+    result: Option<&serde_json::Value>,
+    error: Option<&str>,
+    log: &[String],
+) -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "UPDATE jobs SET state = ?1, finished_at = ?2, result = ?3, error = ?4, log = ?5 WHERE id = ?6",
+        rusqlite::params![
+            state.to_string(),
+            chrono::Utc::now().to_rfc3339(),
+            result.map(|r| r.to_string()),
+            error,
+            log.join("\n"),
+            id
+        ],
+    )?;
+    Ok(())
+}
+
+/// Mark jobs left running by a previous process as failed.
+pub fn fail_interrupted_jobs() -> Result<usize> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "UPDATE jobs SET state = ?1, finished_at = ?2, error = 'Interrupted by restart' WHERE state = ?3",
+        rusqlite::params![
+            JobState::Failed.to_string(),
+            chrono::Utc::now().to_rfc3339(),
+            JobState::Running.to_string()
+        ],
+    )
+}
+
+fn job_from_row(row: &rusqlite::Row, with_log: bool) -> Result<Job> {
+    let parse_err = |e: strum::ParseError| Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e));
+    let job_type: String = row.get("job_type")?;
+    let state: String = row.get("state")?;
+    let result: Option<String> = row.get("result")?;
+    let log: Option<String> = if with_log { row.get("log")? } else { None };
+    Ok(Job {
+        id: row.get("id")?,
+        job_type: job_type.parse().map_err(parse_err)?,
+        state: state.parse().map_err(parse_err)?,
+        target: row.get("target")?,
+        created_at: row.get("created_at")?,
+        finished_at: row.get("finished_at")?,
+        result: result.and_then(|r| serde_json::from_str(&r).ok()),
+        error: row.get("error")?,
+        log: log
+            .map(|log| log.lines().map(String::from).collect())
+            .unwrap_or_default(),
+    })
+}
+
+pub fn return_jobs(limit: u32) -> Result<Vec<Job>> {
+    let conn = Connection::open("data.db")?;
+    let mut stmt = conn.prepare("SELECT * FROM jobs ORDER BY id DESC LIMIT ?1")?;
+    let jobs = stmt.query_map([limit], |row| job_from_row(row, false))?;
+    jobs.collect()
+}
+
+pub fn return_job(id: i64) -> Result<Option<Job>> {
+    let conn = Connection::open("data.db")?;
+    conn.query_row("SELECT * FROM jobs WHERE id = ?1", [id], |row| job_from_row(row, true))
+        .optional()
+}
+
+pub fn return_cached_tags(repository: &str) -> Result<Option<CachedTags>> {
+    let conn = Connection::open("data.db")?;
+    conn.query_row(
+        "SELECT repository, tags, etag, fetched_at FROM tag_cache WHERE repository = ?1",
+        [repository],
+        |row| {
+            let tags: String = row.get(1)?;
+            Ok(CachedTags {
+                repository: row.get(0)?,
+                tags: parse_cached_tags(&tags).map_err(|e| {
+                    Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e))
+                })?,
+                etag: row.get(2)?,
+                fetched_at: row.get(3)?,
+            })
+        },
+    )
+    .optional()
+}
+
+// Entries written before tags carried dates are plain lists of names
+fn parse_cached_tags(tags: &str) -> serde_json::Result<Vec<ImageTag>> {
+    serde_json::from_str(tags).or_else(|e| {
+        serde_json::from_str::<Vec<String>>(tags)
+            .map(|names| {
+                names
+                    .into_iter()
+                    .map(|name| ImageTag { name, published: None })
+                    .collect()
+            })
+            .map_err(|_| e)
+    })
+}
+
+pub fn insert_cached_tags(repository: &str, tags: &[ImageTag], etag: Option<&str>) -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "INSERT OR REPLACE INTO tag_cache (repository, tags, etag, fetched_at) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![
+            repository,
+            serde_json::to_string(tags).unwrap_or_else(|_| "[]".to_string()),
+            etag,
+            chrono::Utc::now().to_rfc3339()
+        ],
+    )?;
+    Ok(())
+}
+
+/// Mark a cached tag list as fresh again after the registry confirmed it is unchanged.
+pub fn touch_cached_tags(repository: &str) -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "UPDATE tag_cache SET fetched_at = ?1 WHERE repository = ?2",
+        [&chrono::Utc::now().to_rfc3339(), repository],
+    )?;
+    Ok(())
+}
+
+/// The image config cached for a tag with when it was fetched.
+pub fn return_cached_config(repository: &str, tag: &str) -> Result<Option<(String, String)>> {
+    let conn = Connection::open("data.db")?;
+    conn.query_row(
+        "SELECT config, fetched_at FROM config_cache WHERE repository = ?1 AND tag = ?2",
+        [repository, tag],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+}
+
+pub fn insert_cached_config(repository: &str, tag: &str, config: &str) -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "INSERT OR REPLACE INTO config_cache (repository, tag, config, fetched_at) VALUES (?1, ?2, ?3, ?4)",
+        [repository, tag, config, &chrono::Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+pub fn insert_ignore(
+    namespace: &str,
+    name: &str,
+    version: Option<&str>,
+    version_range: Option<&str>,
+    until: Option<&str>,
+) -> Result<Ignore> {
+    let conn = Connection::open("data.db")?;
+    let created_at = chrono::Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO ignores (namespace, name, version, version_range, until, created_at)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![namespace, name, version, version_range, until, created_at],
+    )?;
+    Ok(Ignore {
+        id: conn.last_insert_rowid(),
+        namespace: namespace.to_string(),
+        name: name.to_string(),
+        version: version.map(String::from),
+        version_range: version_range.map(String::from),
+        until: until.map(String::from),
+        created_at,
+    })
+}
+
+pub fn return_ignores(namespace: &str, name: &str) -> Result<Vec<Ignore>> {
+    let conn = Connection::open("data.db")?;
+    let mut stmt = conn.prepare(
+        "SELECT id, namespace, name, version, version_range, until, created_at FROM ignores
+                  WHERE namespace = ?1 AND name = ?2 ORDER BY id",
+    )?;
+    let ignores = stmt.query_map([namespace, name], |row| {
+        Ok(Ignore {
+            id: row.get(0)?,
+            namespace: row.get(1)?,
+            name: row.get(2)?,
+            version: row.get(3)?,
+            version_range: row.get(4)?,
+            until: row.get(5)?,
+            created_at: row.get(6)?,
+        })
+    })?;
+    ignores.collect()
+}
+
+/// Delete an ignore of a workload, returning whether it existed.
+pub fn delete_ignore(namespace: &str, name: &str, id: i64) -> Result<bool> {
+    let conn = Connection::open("data.db")?;
+    let deleted = conn.execute(
+        "DELETE FROM ignores WHERE id = ?1 AND namespace = ?2 AND name = ?3",
+        rusqlite::params![id, namespace, name],
+    )?;
+    Ok(deleted > 0)
+}
+
+/// Store releases of a source repository, replacing ones already stored.
+pub fn insert_release_notes(notes: &[ReleaseNote]) -> Result<()> {
+    let mut conn = Connection::open("data.db")?;
+    let tx = conn.transaction()?;
+    for note in notes {
+        tx.execute(
+            "INSERT OR REPLACE INTO release_notes (source, version, title, url, body, published_at)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![note.source, note.version, note.title, note.url, note.body, note.published_at],
+        )?;
+    }
+    tx.commit()
+}
+
+pub fn return_release_notes(source: &str) -> Result<Vec<ReleaseNote>> {
+    let conn = Connection::open("data.db")?;
+    let mut stmt = conn.prepare(
+        "SELECT source, version, title, url, body, published_at FROM release_notes WHERE source = ?1",
+    )?;
+    let notes = stmt.query_map([source], |row| {
+        Ok(ReleaseNote {
+            source: row.get(0)?,
+            version: row.get(1)?,
+            title: row.get(2)?,
+            url: row.get(3)?,
+            body: row.get(4)?,
+            published_at: row.get(5)?,
+        })
+    })?;
+    notes.collect()
+}
+
+/// Record that the releases of a source were fetched without finding `version`.
+pub fn insert_release_note_miss(source: &str, version: &str) -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "INSERT OR REPLACE INTO release_note_misses (source, version, checked_at) VALUES (?1, ?2, ?3)",
+        [source, version, &chrono::Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+/// When the releases of a source were last fetched without finding `version`.
+pub fn return_release_note_miss(source: &str, version: &str) -> Result<Option<String>> {
+    let conn = Connection::open("data.db")?;
+    conn.query_row(
+        "SELECT checked_at FROM release_note_misses WHERE source = ?1 AND version = ?2",
+        [source, version],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Record that an upgrade of a workload to `version` was started without approval.
+pub fn insert_auto_upgrade(namespace: &str, name: &str, version: &str, job_id: i64) -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "INSERT OR REPLACE INTO auto_upgrades (namespace, name, version, job_id, started_at)
+                  VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![namespace, name, version, job_id, chrono::Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+pub fn has_auto_upgrade(namespace: &str, name: &str, version: &str) -> Result<bool> {
+    let conn = Connection::open("data.db")?;
+    conn.query_row(
+        "SELECT 1 FROM auto_upgrades WHERE namespace = ?1 AND name = ?2 AND version = ?3",
+        [namespace, name, version],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|row| row.is_some())
+}
+
+/// Hold a notification, serialized as JSON, until `send_after`.
+pub fn insert_deferred_notification(payload: &str, send_after: &str) -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "INSERT INTO deferred_notifications (payload, send_after) VALUES (?1, ?2)",
+        [payload, send_after],
+    )?;
+    Ok(())
+}
+
+/// Held notifications due at `now` with how often they failed to send, oldest first.
+pub fn return_due_notifications(now: &str) -> Result<Vec<(i64, String, u32)>> {
+    let conn = Connection::open("data.db")?;
+    let mut stmt = conn.prepare(
+        "SELECT id, payload, attempts FROM deferred_notifications WHERE send_after <= ?1 ORDER BY id",
+    )?;
+    let notifications = stmt.query_map([now], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    notifications.collect()
+}
+
+pub fn delete_deferred_notification(id: i64) -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute("DELETE FROM deferred_notifications WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+/// Move a held notification that failed to send to `send_after`, counting the attempt.
+pub fn postpone_deferred_notification(id: i64, send_after: &str) -> Result<()> {
+    let conn = Connection::open("data.db")?;
+    conn.execute(
+        "UPDATE deferred_notifications SET send_after = ?1, attempts = attempts + 1 WHERE id = ?2",
+        rusqlite::params![send_after, id],
+    )?;
+    Ok(())
+}
+
+/// When the next held notification is due.
+pub fn next_deferred_notification() -> Result<Option<String>> {
+    let conn = Connection::open("data.db")?;
+    conn.query_row("SELECT MIN(send_after) FROM deferred_notifications", [], |row| row.get(0))
+}
@@ -1,5 +1,7 @@
-use crate::config::{GitopsConfig, Ntfy, Settings};
-use crate::models::models::Workload;
+use crate::config::{current_settings, GitopsConfig, Ntfy};
+use crate::models::models::{FailureKind, Workload};
+use crate::metrics::GITOPS_COMMITS;
+use crate::notifications::failures::{report_failure, report_success};
 use futures::FutureExt;
 use git2::{
     Commit, Cred, ErrorCode, IndexAddOption, PushOptions, RemoteCallbacks, Repository, Signature,
@@ -10,20 +12,17 @@ use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::Path;
 use k8s_openapi::api::apps::v1::{Deployment, StatefulSet};
-use crate::notifications::ntfy::notify_commit;
+use crate::secrets::resolve;
+use crate::repocheck::repocheck::{strip_tag, upstream_repository_of};
 
 
 
 fn load_settings() ->Result<Vec<GitopsConfig>, String> {
     //get settings
-    let settings = Settings::new().unwrap_or_else(|err| {
-        log::error!("Failed to load settings: {}", err);
-        panic!("Failed to load settings: {}", err);
-    });
-    if let Some(gitops_config) = settings.gitops {
-        return Ok(gitops_config.clone());
+    if let Some(gitops_config) = &current_settings().gitops {
+        Ok(gitops_config.clone())
     } else {
-        return Err("No Gitops Config Found".to_string());
+        Err("No Gitops Config Found".to_string())
     }
 
 }
@@ -84,14 +83,11 @@ fn edit_files(local_path: &Path, workload: &Workload) {
     };
     let image = Some(workload.image.clone());
     let current_version = Some(workload.current_version.clone());
-    let latest_version = Some(workload.latest_version.clone());
+    let latest_version = workload.latest_version.clone();
     //split image to get base image
     let image_copy = image.clone().unwrap();
-    //use latest_version tag to make new image name
-    let base_image = image_copy.split(":").collect::<Vec<&str>>()[0];
-    let new_image = format!("{}:{}", base_image, latest_version.unwrap());
+    let base_image = strip_tag(&image_copy);
     log::info!("Base image: {}", &base_image);
-    log::info!("New image: {}", &new_image);
     //list files
     for entry in WalkDir::new(search_path).into_iter().filter_map(|e| e.ok()) {
         log::info!("Entry: {:?}", entry.path());
@@ -108,9 +104,11 @@ fn edit_files(local_path: &Path, workload: &Workload) {
                     if let Some(template_spec) = spec.template.spec.as_mut() {
                         for container in &mut template_spec.containers {
                             // Replace image in StatefulSet
-                            if container.image.as_ref().unwrap().contains(&base_image) {
+                            let manifest_image = container.image.clone().unwrap_or_default();
+                            if let Some(new_image) = rewrite_image(&manifest_image, &image_copy, &latest_version) {
                                 log::info!("Found target image in file: {:?}", entry.path());
-                                container.image = Some(new_image.clone());
+                                log::info!("New image: {}", &new_image);
+                                container.image = Some(new_image);
                                 image_updated = true; // Image has been updated
 
                                 //Set flag that image has been updated
@@ -140,9 +138,11 @@ fn edit_files(local_path: &Path, workload: &Workload) {
                     if let Some(template_spec) = spec.template.spec.as_mut() {
                         for container in &mut template_spec.containers {
                             // Replace image in Deployment
-                            if container.image.as_ref().unwrap().contains(&base_image) {
+                            let manifest_image = container.image.clone().unwrap_or_default();
+                            if let Some(new_image) = rewrite_image(&manifest_image, &image_copy, &latest_version) {
                                 log::info!("Found target image in file: {:?}", entry.path());
-                                container.image = Some(new_image.clone());
+                                log::info!("New image: {}", &new_image);
+                                container.image = Some(new_image);
                                 image_updated = true; // Image has been updated
                             }
                         }
@@ -166,6 +166,18 @@ fn edit_files(local_path: &Path, workload: &Workload) {
     }
 }
 
+/// The manifest image with its tag replaced by `new_tag` if it refers to the same repository as
+/// the running workload image, directly or through a registry mirror. The registry prefix used
+/// in the manifest is kept, so a manifest naming the upstream image is not switched to the mirror.
+fn rewrite_image(manifest_image: &str, workload_image: &str, new_tag: &str) -> Option<String> {
+    let manifest_base = strip_tag(manifest_image);
+    let same_repository = matches!(
+        (upstream_repository_of(manifest_image), upstream_repository_of(workload_image)),
+        (Ok(manifest), Ok(workload)) if manifest == workload
+    );
+    same_repository.then(|| format!("{}:{}", manifest_base, new_tag))
+}
+
 fn stage_changes(repo: &Repository) -> Result<(), git2::Error> {
     let mut index = repo.index()?;
     index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
@@ -184,7 +196,7 @@ fn commit_changes<'a>(
     let tree = repo.find_tree(oid)?;
     let parent_commit = find_last_commit(repo)?;
     let commit = repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent_commit])?;
-    Ok(repo.find_commit(commit)?)
+    repo.find_commit(commit)
 }
 
 fn find_last_commit(repo: &Repository) -> Result<Commit<'_>, git2::Error> {
@@ -213,7 +225,27 @@ pub async fn run_git_operations(workload: Workload) -> Result<(), Box<dyn Error>
     match load_settings() {
         Ok(settings) => {
             log::info!("Settings: {:?}", settings);
-            return Ok(run_git_operations_internal(settings, workload).await?);
+            let repo = workload.git_ops_repo.clone().unwrap_or_default();
+            // Failures are tracked per repository, or per workload when it names none
+            let subject = match &workload.git_ops_repo {
+                Some(repo) => repo.clone(),
+                None => format!("{}/{}", workload.namespace, workload.name),
+            };
+            let result = run_git_operations_internal(settings, workload)
+                .await
+                .map_err(|e| e.to_string());
+            return match result {
+                Ok(_) => {
+                    GITOPS_COMMITS.with_label_values(&[repo.as_str(), "success"]).inc();
+                    report_success(FailureKind::Gitops, &subject).await;
+                    Ok(())
+                }
+                Err(e) => {
+                    GITOPS_COMMITS.with_label_values(&[repo.as_str(), "failure"]).inc();
+                    report_failure(FailureKind::Gitops, &subject, &e).await;
+                    Err(e.into())
+                }
+            };
         }
         Err(e) => {
             log::info!("Failed to load settings: {}", e);
@@ -239,15 +271,16 @@ async fn run_git_operations_internal(
             );
             continue;
         }
+        let access_token = resolve(&gitops_config.access_token()).unwrap_or_default();
         let commit_name = gitops_config.commit_name;
         let commit_email = gitops_config.commit_email;
-        let commit_message = gitops_config.commit_message;
+        let commit_message = match &workload.changelog_url {
+            Some(url) => format!("{}\n\nRelease notes: {}", gitops_config.commit_message, url),
+            None => gitops_config.commit_message,
+        };
         let repo_url = gitops_config.repository_url;
         let branch = gitops_config.branch;
         let name = gitops_config.name;
-        let access_token_env_name = gitops_config.access_token_env_name;
-        let access_token = std::env::var(access_token_env_name).unwrap_or_default();
-        log::info!("Access token: {}", access_token);
         let local_path = Path::new("/tmp/repos/").join(name);
         log::info!("Running git operations for repository: {}", repo_url);
         log::info!("Local path: {:?}", local_path);
@@ -258,10 +291,31 @@ async fn run_git_operations_internal(
         stage_changes(&repo)?;
         commit_changes(&repo, &commit_message, &commit_name, &commit_email)?;
         push_changes(&repo, &access_token)?;
-        notify_commit(&workload).await?;
 
     }
 
     Ok(())
 }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_image() {
+        assert_eq!(rewrite_image("postgres:16.1.0", "postgres:16.1.0", "16.2.0").as_deref(), Some("postgres:16.2.0"));
+        assert_eq!(
+            rewrite_image("docker.io/library/postgres:16.1.0", "postgres:16.1.0", "16.2.0").as_deref(),
+            Some("docker.io/library/postgres:16.2.0")
+        );
+        assert_eq!(
+            rewrite_image("registry:5000/app:1.2.3", "registry:5000/app:1.2.3", "1.3.0").as_deref(),
+            Some("registry:5000/app:1.3.0")
+        );
+        // Lookalike repositories are left alone
+        assert_eq!(rewrite_image("prometheuscommunity/postgres-exporter:0.15.0", "postgres:16.1.0", "16.2.0"), None);
+        assert_eq!(rewrite_image("postgres-exporter:0.15.0", "postgres:16.1.0", "16.2.0"), None);
+        assert_eq!(rewrite_image("registry:5000/app:1.2.3", "registry:5001/app:1.2.3", "1.3.0"), None);
+    }
+}
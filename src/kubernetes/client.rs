@@ -1,7 +1,9 @@
 // kubernetes/client.rs
 use crate::models::models::{UpdateStatus, Workload};
 use futures::future::join_all;
-use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::api::apps::v1::{Deployment, StatefulSet};
+use k8s_openapi::api::core::v1::{Node, Pod, Secret};
+use oci_distribution::Reference;
 use kube::{
     api::{Api, ListParams},
     Client as KubeClient, Error as KubeError, ResourceExt,
@@ -18,15 +20,89 @@ impl Client {
         Ok(Client { kube_client })
     }
 
+    pub async fn apiserver_version(&self) -> Result<(), KubeError> {
+        self.kube_client.apiserver_version().await.map(|_| ())
+    }
+
+    pub async fn list_nodes(&self) -> Result<Vec<Node>, KubeError> {
+        let nodes: Api<Node> = Api::all(self.kube_client.clone());
+        nodes
+            .list(&ListParams::default())
+            .await
+            .map(|node_list| node_list.items)
+    }
+
+    /// Architecture of each node by node name, from the `kubernetes.io/arch` label.
+    /// Empty when nodes cannot be listed, architecture checks are then skipped.
+    pub async fn node_architectures(&self) -> BTreeMap<String, String> {
+        match self.list_nodes().await {
+            Ok(nodes) => nodes
+                .into_iter()
+                .filter_map(|node| {
+                    let arch = node.labels().get("kubernetes.io/arch")?.clone();
+                    Some((node.name_any(), arch))
+                })
+                .collect(),
+            Err(e) => {
+                log::warn!("Failed to list nodes, skipping architecture checks: {}", e);
+                BTreeMap::new()
+            }
+        }
+    }
+
     pub async fn list_pods(&self) -> Result<Vec<Pod>, KubeError> {
         let pods: Api<Pod> = Api::all(self.kube_client.clone());
         pods.list(&ListParams::default())
             .await
             .map(|pod_list| pod_list.items)
     }
+
+    /// Pods in `namespace` matching a label selector such as `app=postgres,tier in (db)`.
+    pub async fn list_selected_pods(&self, namespace: &str, selector: &str) -> Result<Vec<Pod>, KubeError> {
+        let pods: Api<Pod> = Api::namespaced(self.kube_client.clone(), namespace);
+        pods.list(&ListParams::default().labels(selector))
+            .await
+            .map(|pod_list| pod_list.items)
+    }
+
+    pub async fn list_deployments(&self, namespace: &str) -> Result<Vec<Deployment>, KubeError> {
+        let deployments: Api<Deployment> = Api::namespaced(self.kube_client.clone(), namespace);
+        deployments
+            .list(&ListParams::default())
+            .await
+            .map(|deployment_list| deployment_list.items)
+    }
+
+    pub async fn list_stateful_sets(&self, namespace: &str) -> Result<Vec<StatefulSet>, KubeError> {
+        let stateful_sets: Api<StatefulSet> = Api::namespaced(self.kube_client.clone(), namespace);
+        stateful_sets
+            .list(&ListParams::default())
+            .await
+            .map(|stateful_set_list| stateful_set_list.items)
+    }
+
+    pub async fn get_deployment(&self, namespace: &str, name: &str) -> Result<Deployment, KubeError> {
+        let deployments: Api<Deployment> = Api::namespaced(self.kube_client.clone(), namespace);
+        deployments.get(name).await
+    }
+
+    pub async fn get_stateful_set(&self, namespace: &str, name: &str) -> Result<StatefulSet, KubeError> {
+        let stateful_sets: Api<StatefulSet> = Api::namespaced(self.kube_client.clone(), namespace);
+        stateful_sets.get(name).await
+    }
+
+    /// A key of a Secret, None when the Secret does not exist or lacks the key.
+    pub async fn read_secret(&self, namespace: &str, name: &str, key: &str) -> Result<Option<String>, KubeError> {
+        let secrets: Api<Secret> = Api::namespaced(self.kube_client.clone(), namespace);
+        let secret = secrets.get_opt(name).await?;
+        Ok(secret
+            .and_then(|secret| secret.data)
+            .and_then(|mut data| data.remove(key))
+            .map(|value| String::from_utf8_lossy(&value.0).trim_end().to_string()))
+    }
 }
 
-async fn create_workload_from_pod(pod: Pod) -> Option<Workload> {
+async fn create_workload_from_pod(pod: Pod, node_arches: &BTreeMap<String, String>) -> Option<Workload> {
     let annotations = pod.metadata.annotations.as_ref()?;
     if annotations.get("slackwatch.enable") != Some(&"true".to_string()) {
         return None;
@@ -37,19 +113,34 @@ async fn create_workload_from_pod(pod: Pod) -> Option<Workload> {
     let container = spec.containers.first()?;
     let name = container.name.clone();
     let image = container.image.clone().unwrap_or_default();
-    let image_parts: Vec<&str> = image.split(':').collect();
-    let current_version = image_parts.get(1).unwrap_or(&"latest").to_string();
+    // Parsed so a port in the registry host is not taken for the tag
+    let current_version = Reference::try_from(image.as_str())
+        .ok()
+        .and_then(|reference| reference.tag().map(String::from))
+        .unwrap_or_else(|| "latest".to_string());
+    let arch = annotations.get("slackwatch.arch").cloned().or_else(|| {
+        let node = spec.node_name.as_ref()?;
+        node_arches.get(node).cloned()
+    });
 
     Some(Workload {
         name: name.clone(),
         namespace: namespace.clone(),
-        image: image,
-        current_version: current_version, // Simplified for demonstration
+        image,
+        current_version, // Simplified for demonstration
         latest_version: "1.0.0".to_string(), // Simplified for demonstration
         exclude_pattern: annotations.get("slackwatch.exclude").cloned(),
         include_pattern: annotations.get("slackwatch.include").cloned(),
         git_ops_repo: annotations.get("slackwatch.repo").cloned(),
         git_directory: annotations.get("slackwatch.directory").cloned(),
+        min_age: annotations.get("slackwatch.min_age").cloned(),
+        arch,
+        source: annotations.get("slackwatch.source").cloned(),
+        changelog_url: None,
+        current_metadata: None,
+        latest_metadata: None,
+        schedule: annotations.get("slackwatch.schedule").cloned(),
+        auto_upgrade: annotations.get("slackwatch.auto_upgrade").cloned(),
         update_available: UpdateStatus::NotAvailable, // Default value, adjust as needed
         last_scanned: chrono::Utc::now().to_rfc3339(),
     })
@@ -61,8 +152,9 @@ pub async fn find_specific_workload(
 ) -> Result<Workload, KubeError> {
     let client = Client::new().await?;
     let pods = client.list_pods().await?;
+    let node_arches = client.node_architectures().await;
     for pod in pods {
-        if let Some(workload) = create_workload_from_pod(pod).await {
+        if let Some(workload) = create_workload_from_pod(pod, &node_arches).await {
             if workload.name == request_name && workload.namespace == request_namespace {
                 return Ok(workload);
             }
@@ -79,18 +171,19 @@ pub async fn find_specific_workload(
 pub async fn find_enabled_workloads() -> Result<Vec<Workload>, KubeError> {
     let client = Client::new().await?;
     let pods = client.list_pods().await?;
+    let node_arches = client.node_architectures().await;
 
     // Map pods to a Vec of Futures
     let futures: Vec<_> = pods
         .into_iter()
-        .map(|pod| create_workload_from_pod(pod.clone()))
+        .map(|pod| create_workload_from_pod(pod.clone(), &node_arches))
         .collect();
 
     // Await all futures and filter out None values
     let workloads: Vec<Workload> = join_all(futures)
         .await
         .into_iter()
-        .filter_map(|workload_option| workload_option)
+        .flatten()
         .collect();
 
     Ok(workloads)
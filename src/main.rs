@@ -2,17 +2,20 @@
 
 use std::env;
 use log::info;
-use crate::config::Settings;
+use crate::config::{replace_settings, Settings};
 
 mod config;
 mod database;
 mod gitops;
 mod kubernetes;
+mod metrics;
 mod models;
 mod notifications;
 mod repocheck;
+mod secrets;
 mod services;
 mod api;
+mod auth;
 
 #[tokio::main]
 async fn main() {
@@ -27,14 +30,31 @@ async fn main() {
 
     log::info!("Starting up");
     log::info!("Loading configuration {:?}", settings);
-
-    use crate::database::client::create_table_if_not_exist;
+    if let Err(errors) = settings.validate() {
+        for error in &errors {
+            log::error!("Invalid configuration: {}", error);
+        }
+        panic!("Invalid configuration, {} problems found", errors.len());
+    }
+    replace_settings(settings.clone());
+
+    use crate::database::client::{create_table_if_not_exist, fail_interrupted_jobs};
     create_table_if_not_exist().unwrap();
+    match fail_interrupted_jobs() {
+        Ok(0) => {}
+        Ok(count) => log::warn!("Marked {} jobs interrupted by the last shutdown as failed", count),
+        Err(e) => log::error!("Failed to clean up interrupted jobs: {}", e),
+    }
+
+    services::reload::refresh_secrets(&settings).await;
+    repocheck::repocheck::configure(&settings);
+    services::reload::spawn_config_watcher();
+    tokio::task::spawn(services::reload::check_workloads());
 
     // Start the scheduler in a separate task
-    tokio::task::spawn(services::scheduler::run_scheduler(settings.clone()));
+    services::scheduler::spawn_scheduler(settings.clone());
 
     // Start the API server
     log::info!("Starting API server");
-    api::start_api_server().await;
+    api::start_api_server(settings).await;
 }
@@ -0,0 +1,124 @@
+use crate::database::client::return_all_workloads;
+use crate::models::models::UpdateStatus;
+use crate::services::workloads::update_level;
+use prometheus::{Encoder, Gauge, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::sync::LazyLock;
+
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static WORKLOADS_WATCHED: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new("slackwatch_workloads_watched", "Number of workloads watched by slackwatch").unwrap())
+});
+
+pub static UPDATES_AVAILABLE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new("slackwatch_update_available", "Set to 1 for each workload with an update available"),
+            &["namespace", "name", "update_level"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static SCAN_DURATION: LazyLock<Gauge> = LazyLock::new(|| {
+    register(Gauge::new("slackwatch_scan_duration_seconds", "Duration of the last full scan").unwrap())
+});
+
+pub static LAST_SUCCESSFUL_SCAN: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(
+        IntGauge::new(
+            "slackwatch_last_successful_scan_timestamp_seconds",
+            "Unix time of the last full scan that completed without error",
+        )
+        .unwrap(),
+    )
+});
+
+pub static SCHEDULER_HEARTBEAT: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(
+        IntGauge::new(
+            "slackwatch_scheduler_heartbeat_timestamp_seconds",
+            "Unix time the scheduler loop last reported it was running",
+        )
+        .unwrap(),
+    )
+});
+
+pub static ROLLOUTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("slackwatch_rollouts_total", "Upgrades watched until they rolled out, failed or timed out"),
+            &["result"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static REGISTRY_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("slackwatch_registry_requests_total", "Requests made to container registries"),
+            &["registry"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static REGISTRY_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("slackwatch_registry_errors_total", "Failed requests to container registries"),
+            &["registry"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static GITOPS_COMMITS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("slackwatch_gitops_commits_total", "Gitops commit and push attempts"),
+            &["repo", "result"],
+        )
+        .unwrap(),
+    )
+});
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: T) -> T {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Failed to register metric");
+    metric
+}
+
+/// Render all metrics in the Prometheus text format. Workload gauges are rebuilt
+/// from the database on every scrape so they always match the latest scan.
+pub fn gather() -> Result<String, String> {
+    let workloads = return_all_workloads().map_err(|e| e.to_string())?;
+    WORKLOADS_WATCHED.set(workloads.len() as i64);
+    UPDATES_AVAILABLE.reset();
+    for workload in workloads
+        .iter()
+        .filter(|w| w.update_available == UpdateStatus::Available)
+    {
+        let level = update_level(&workload.current_version, &workload.latest_version)
+            .map(|level| level.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        UPDATES_AVAILABLE
+            .with_label_values(&[workload.namespace.as_str(), workload.name.as_str(), level.as_str()])
+            .set(1);
+    }
+    // Touch the remaining metrics so they are exported before their first update
+    LazyLock::force(&SCAN_DURATION);
+    LazyLock::force(&LAST_SUCCESSFUL_SCAN);
+    LazyLock::force(&REGISTRY_REQUESTS);
+    LazyLock::force(&REGISTRY_ERRORS);
+    LazyLock::force(&GITOPS_COMMITS);
+    LazyLock::force(&ROLLOUTS);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .map_err(|e| e.to_string())?;
+    String::from_utf8(buffer).map_err(|e| e.to_string())
+}
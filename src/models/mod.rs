@@ -1,2 +1,3 @@
 pub mod gitops_models;
+#[allow(clippy::module_inception)]
 pub mod models;
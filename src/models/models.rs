@@ -15,6 +15,78 @@ pub struct Workload {
     pub namespace: String,
     pub current_version: String,
     pub latest_version: String,
+    /// From the `slackwatch.min_age` annotation, only used while scanning and not stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<String>,
+    /// Comma-separated architectures the workload runs on, from the `slackwatch.arch`
+    /// annotation or its node's `kubernetes.io/arch` label. Not stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// Source repository of the image, from the `slackwatch.source` annotation or the
+    /// `org.opencontainers.image.source` label
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Release notes of the latest version, or the source's release list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changelog_url: Option<String>,
+    /// OCI labels of the running tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_metadata: Option<ImageMetadata>,
+    /// OCI labels of the latest tag, read once an update is found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest_metadata: Option<ImageMetadata>,
+    /// Named schedule the workload is scanned on, from the `slackwatch.schedule` annotation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    /// Largest update applied without approval, from the `slackwatch.auto_upgrade`
+    /// annotation. Only used after a scheduled scan and not stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_upgrade: Option<String>,
+}
+
+#[cfg(test)]
+impl Workload {
+    /// Workload in the default namespace with only its image and versions set, for tests
+    /// to fill in the rest with struct update syntax.
+    pub fn for_test(name: &str, image: &str, current: &str, latest: &str) -> Self {
+        Workload {
+            name: name.to_string(),
+            exclude_pattern: None,
+            git_ops_repo: None,
+            include_pattern: None,
+            update_available: UpdateStatus::NotAvailable,
+            git_directory: None,
+            image: image.to_string(),
+            last_scanned: String::new(),
+            namespace: "default".to_string(),
+            current_version: current.to_string(),
+            latest_version: latest.to_string(),
+            min_age: None,
+            arch: None,
+            source: None,
+            changelog_url: None,
+            current_metadata: None,
+            latest_metadata: None,
+            schedule: None,
+            auto_upgrade: None,
+        }
+    }
+}
+
+//Data model for the standard `org.opencontainers.image.*` labels of an image
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ImageMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    /// Build date from the label, or the `created` field of the image config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub licenses: Option<String>,
 }
 
 #[derive(strum_macros::Display, strum_macros::EnumString, Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -23,6 +95,113 @@ pub enum UpdateStatus {
     NotAvailable,
 }
 
+#[derive(strum_macros::Display, strum_macros::EnumString, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum UpdateLevel {
+    Major,
+    Minor,
+    Patch,
+}
+
+#[derive(strum_macros::Display, strum_macros::EnumString, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum FailureKind {
+    Scan,
+    RegistryAuth,
+    Gitops,
+    /// An invalid annotation keeping a workload from being scanned
+    Annotation,
+}
+
+// Ordered so that a role satisfies every role below it
+#[derive(strum_macros::Display, strum_macros::EnumString, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Maintainer,
+}
+
+#[derive(strum_macros::Display, strum_macros::EnumString, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum JobType {
+    ScanAll,
+    ScanOne,
+    Upgrade,
+}
+
+#[derive(strum_macros::Display, strum_macros::EnumString, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+//Data model for a background job
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Job {
+    pub id: i64,
+    pub job_type: JobType,
+    pub state: JobState,
+    pub target: Option<String>,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub log: Vec<String>,
+}
+
+//Data model for an image tag, with its push date when the registry reports one
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ImageTag {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published: Option<String>,
+}
+
+//Data model for a cached registry tag list
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedTags {
+    pub repository: String,
+    pub tags: Vec<ImageTag>,
+    pub etag: Option<String>,
+    pub fetched_at: String,
+}
+
+//Data model for an ignored version, version range or snoozed workload
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Ignore {
+    pub id: i64,
+    pub namespace: String,
+    pub name: String,
+    /// An exact tag to skip
+    pub version: Option<String>,
+    /// A SemVer requirement such as `>=17, <18`, matching tags are skipped
+    pub version_range: Option<String>,
+    /// RFC 3339 time the ignore expires. Without version or range every update is snoozed until then
+    pub until: Option<String>,
+    pub created_at: String,
+}
+
+//Data model for a release published on GitHub or GitLab
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReleaseNote {
+    /// Source repository URL the release belongs to
+    pub source: String,
+    /// Git tag of the release
+    pub version: String,
+    pub title: Option<String>,
+    pub url: String,
+    pub body: Option<String>,
+    pub published_at: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ApiResponse {
     pub(crate) status: String,
@@ -0,0 +1,62 @@
+use crate::config::{current_settings, FailureAlerts};
+use crate::database::client::{clear_failures, record_failure};
+use crate::models::models::FailureKind;
+use crate::notifications::ntfy::notify_failure;
+
+fn load_settings() -> FailureAlerts {
+    current_settings()
+        .notifications
+        .as_ref()
+        .map(|notifications| notifications.failures.clone())
+        .unwrap_or_default()
+}
+
+fn threshold(settings: &FailureAlerts, kind: FailureKind) -> u32 {
+    match kind {
+        FailureKind::Scan => settings.scan_threshold,
+        FailureKind::RegistryAuth => settings.auth_threshold,
+        FailureKind::Gitops => settings.gitops_threshold,
+        FailureKind::Annotation => settings.annotation_threshold,
+    }
+}
+
+/// Record a failure for `subject` (an image, gitops repository or workload) and alert once the
+/// consecutive failure count reaches the configured threshold.
+pub async fn report_failure(kind: FailureKind, subject: &str, error: &str) {
+    let count = match record_failure(&kind, subject, error) {
+        Ok(count) => count,
+        Err(e) => {
+            log::error!("Failed to record {} failure for {}: {}", kind, subject, e);
+            return;
+        }
+    };
+    let settings = load_settings();
+    let threshold = threshold(&settings, kind);
+    log::info!("{} failure {} of {} for {}", kind, count, threshold, subject);
+    if threshold > 0 && count == threshold {
+        let message = format!("{} failed {} times in a row: {}", subject, count, error);
+        notify_failure(&settings, kind, subject, &message)
+            .await
+            .unwrap_or_else(|e| log::error!("Error sending failure notification: {}", e));
+    }
+}
+
+/// Reset the failure count for `subject`, sending a recovery notification if an
+/// alert had already been raised.
+pub async fn report_success(kind: FailureKind, subject: &str) {
+    let count = match clear_failures(&kind, subject) {
+        Ok(count) => count,
+        Err(e) => {
+            log::error!("Failed to clear {} failures for {}: {}", kind, subject, e);
+            return;
+        }
+    };
+    let settings = load_settings();
+    let threshold = threshold(&settings, kind);
+    if threshold > 0 && count >= threshold {
+        let message = format!("{} recovered after {} failures", subject, count);
+        notify_failure(&settings, kind, subject, &message)
+            .await
+            .unwrap_or_else(|e| log::error!("Error sending recovery notification: {}", e));
+    }
+}
@@ -1 +1,3 @@
+pub mod failures;
 pub mod ntfy;
+pub mod routing;
@@ -1,22 +1,175 @@
 use futures::SinkExt;
-use crate::config::{Ntfy, Settings};
-use crate::models::models::Workload;
+use crate::config::{current_settings, FailureAlerts, Ntfy, Settings};
+use crate::models::models::{FailureKind, Workload};
+use crate::notifications::routing::{load_rules, resolve_route, Route};
+use crate::database::client::{
+    delete_deferred_notification, insert_deferred_notification, postpone_deferred_notification,
+    return_due_notifications,
+};
+use crate::secrets::resolve;
+use crate::services::scheduler::restart_scheduler;
+use crate::services::windows::quiet_until;
+use crate::services::workloads::update_level;
 use ntfy::payload::{Action, ActionType};
-use ntfy::{dispatcher, Auth, Dispatcher, Payload, Priority};
+use ntfy::{dispatcher, Auth, Dispatcher, DispatcherBuilder, Payload, Priority};
 use ntfy::error::Error as NtfyError;
 
+// Apply the routing rules for this workload. Returns None when the notification is
+// silenced or routed to a notifier other than ntfy.
+fn route_for(workload: &Workload) -> Option<Route> {
+    let level = update_level(&workload.current_version, &workload.latest_version);
+    let route = resolve_route(&load_rules(), workload, level);
+    match route {
+        Some(route) if route.notifier == "ntfy" => Some(route),
+        Some(route) => {
+            log::warn!("Unknown notifier {} for workload {}", route.notifier, workload.name);
+            None
+        }
+        None => {
+            log::info!("Notification for {} silenced by routing rule", workload.name);
+            None
+        }
+    }
+}
+
+// Authenticates with the token when one is configured
+fn dispatcher_builder(settings: &Ntfy) -> DispatcherBuilder {
+    let builder = dispatcher::builder(&settings.url);
+    match resolve(&settings.token()) {
+        Some(token) => builder.credentials(Auth::credentials("", token)),
+        None => builder,
+    }
+}
+
+// How long a held notification that failed to send waits before it is tried again
+const RETRY_DELAY_MINUTES: i64 = 5;
+
+// Attempts after which a held notification is dropped, two hours of retries
+const MAX_SEND_ATTEMPTS: u32 = 24;
+
+// When a notification below the urgent priority is held until quiet hours end, after
+// `store` saved it as JSON with that time. None when it is sent right away.
+fn hold_until(
+    settings: &Settings,
+    payload: &Payload,
+    now: chrono::DateTime<chrono::Utc>,
+    store: impl FnOnce(&str, &str) -> Result<(), String>,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let until = quiet_until(settings, payload.priority, now)?;
+    let stored = serde_json::to_string(payload)
+        .map_err(|e| e.to_string())
+        .and_then(|json| store(&json, &until.to_rfc3339()));
+    match stored {
+        Ok(()) => Some(until),
+        Err(e) => {
+            log::error!("Failed to hold notification for quiet hours, sending it now: {}", e);
+            None
+        }
+    }
+}
+
+// Hold a notification below the urgent priority during quiet hours. The scheduler sends
+// it when they end, returns whether it was held.
+fn held_for_quiet_hours(payload: &Payload) -> bool {
+    let store = |json: &str, until: &str| insert_deferred_notification(json, until).map_err(|e| e.to_string());
+    match hold_until(&current_settings(), payload, chrono::Utc::now(), store) {
+        Some(until) => {
+            log::info!("Quiet hours, holding notification {:?} until {}", payload.title, until);
+            restart_scheduler();
+            true
+        }
+        None => false,
+    }
+}
+
+// What became of a held notification
+#[derive(Debug, PartialEq)]
+enum Delivery {
+    Sent,
+    Unreadable(String),
+    Failed(String),
+    GaveUp(String),
+}
+
+// Send held notifications, stored as JSON by their id with the attempts made before
+fn deliver(held: Vec<(i64, String, u32)>, mut send: impl FnMut(&Payload) -> Result<(), String>) -> Vec<(i64, Delivery)> {
+    held.into_iter()
+        .map(|(id, json, attempts)| {
+            let delivery = match serde_json::from_str::<Payload>(&json) {
+                Ok(payload) => match send(&payload) {
+                    Ok(()) => Delivery::Sent,
+                    Err(e) if attempts + 1 >= MAX_SEND_ATTEMPTS => Delivery::GaveUp(e),
+                    Err(e) => Delivery::Failed(e),
+                },
+                Err(e) => Delivery::Unreadable(e.to_string()),
+            };
+            (id, delivery)
+        })
+        .collect()
+}
+
+/// Send the notifications held during quiet hours that have ended. One that fails to send
+/// is kept and tried again a few minutes later, until it has failed `MAX_SEND_ATTEMPTS` times.
+pub async fn send_deferred() {
+    let now = chrono::Utc::now();
+    let due = match return_due_notifications(&now.to_rfc3339()) {
+        Ok(due) if due.is_empty() => return,
+        Ok(due) => due,
+        Err(e) => {
+            log::error!("Failed to load held notifications: {}", e);
+            return;
+        }
+    };
+    let dispatcher = load_settings().and_then(|settings| {
+        dispatcher_builder(&settings).build_blocking().map_err(|e| e.to_string())
+    });
+    log::info!("Quiet hours ended, sending {} held notifications", due.len());
+    let retry_at = (now + chrono::Duration::minutes(RETRY_DELAY_MINUTES)).to_rfc3339();
+    let delivered = deliver(due, |payload| {
+        let dispatcher = dispatcher.as_ref().map_err(|e| e.clone())?;
+        dispatcher.send(&payload.clone().markdown(true)).map_err(|e| e.to_string())
+    });
+    for (id, delivery) in delivered {
+        let stored = match delivery {
+            Delivery::Sent => {
+                log::info!("Held notification {} sent.", id);
+                delete_deferred_notification(id)
+            }
+            Delivery::Unreadable(e) => {
+                log::error!("Dropping unreadable held notification {}: {}", id, e);
+                delete_deferred_notification(id)
+            }
+            Delivery::Failed(e) => {
+                log::warn!("Failed to send held notification {}, retrying at {}: {}", id, retry_at, e);
+                postpone_deferred_notification(id, &retry_at)
+            }
+            Delivery::GaveUp(e) => {
+                log::error!("Dropping held notification {} after {} failed attempts: {}", id, MAX_SEND_ATTEMPTS, e);
+                delete_deferred_notification(id)
+            }
+        };
+        stored.unwrap_or_else(|e| log::error!("Failed to update held notification {}: {}", id, e));
+    }
+}
+
+// Append a link to the release notes of the new version when one was found
+fn with_changelog(message: String, workload: &Workload) -> String {
+    match &workload.changelog_url {
+        Some(url) => format!("{}\n\n[Release notes]({})", message, url),
+        None => message,
+    }
+}
+
 pub async fn notify_commit(workload: &Workload) -> Result<(), NtfyError> {
+    let Some(route) = route_for(workload) else {
+        return Ok(());
+    };
     //get settings
     match load_settings() {
         Ok(settings) => {
-            let url = settings.url;
-            let topic = settings.topic;
-            let token = settings.token;
-
-
-            let dispatcher = dispatcher::builder(&url)
-                .credentials(Auth::credentials("", &token)) // Add optional credentials
-                .build_blocking()?; // Build dispatcher
+            let url = settings.url.clone();
+            let topic = route.topic.unwrap_or_else(|| settings.topic.clone());
+            let dispatcher = dispatcher_builder(&settings).build_blocking()?; // Build dispatcher
 
             //let action = Action::new(
             //    ActionType::Http,
@@ -25,22 +178,28 @@ pub async fn notify_commit(workload: &Workload) -> Result<(), NtfyError> {
             //);
 
             //make message for payload about new container update
-            let message = format!(
-                "Deployment {} has been updated to version {}",
-                workload.name, workload.latest_version
+            let message = with_changelog(
+                format!(
+                    "Deployment {} has been updated to version {}",
+                    workload.name, workload.latest_version
+                ),
+                workload,
             );
 
             let payload = Payload::new(&topic)
                 .message(message) // Add optional message
                 .title(&workload.name) // Add optiona title
                 .tags(["Update"]) // Add optional tags
-                .priority(Priority::Default) // Edit priority
+                .priority(route.priority.unwrap_or(Priority::Default)) // Edit priority
                 //.actions([action]) // Add optional actions
                 //.click(Url::parse("https://example.com")?) // Add optional clickable url
                 //.attach(Url::parse("https://example.com/file.jpg")?) // Add optional url attachment
                 //.delay(Local::now() + Duration::minutes(1)) // Add optional delay
                 .markdown(true); // Use markdown
 
+            if held_for_quiet_hours(&payload) {
+                return Ok(());
+            }
             match dispatcher.send(&payload) {
                 Ok(_) => log::info!("Payload sent successfully."),
                 Err(e) => log::error!("Failed to send payload: {}", e),
@@ -58,35 +217,73 @@ pub async fn notify_commit(workload: &Workload) -> Result<(), NtfyError> {
 
 }
 
+/// Report whether an upgrade rolled out, once its pods were watched.
+pub async fn notify_rollout(workload: &Workload, outcome: &Result<(), String>) -> Result<(), NtfyError> {
+    let Some(route) = route_for(workload) else {
+        return Ok(());
+    };
+    let settings = match load_settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            log::info!("Failed to load settings: {}", e);
+            return Ok(());
+        }
+    };
+    let topic = route.topic.unwrap_or_else(|| settings.topic.clone());
+    let dispatcher = dispatcher_builder(&settings).build_blocking()?;
+    let payload = match outcome {
+        Ok(()) => Payload::new(&topic)
+            .message(with_changelog(
+                format!("Deployment {} has rolled out version {}", workload.name, workload.latest_version),
+                workload,
+            ))
+            .tags(["Update"])
+            .priority(route.priority.unwrap_or(Priority::Default)),
+        Err(e) => Payload::new(&topic)
+            .message(format!(
+                "Rollout of {} to version {} failed: {}",
+                workload.name, workload.latest_version, e
+            ))
+            .tags(["warning"])
+            .priority(route.priority.unwrap_or(Priority::High)),
+    }
+    .title(&workload.name)
+    .markdown(true);
+
+    if held_for_quiet_hours(&payload) {
+        return Ok(());
+    }
+    match dispatcher.send(&payload) {
+        Ok(_) => log::info!("Rollout notification sent."),
+        Err(e) => log::error!("Failed to send rollout notification: {}", e),
+    }
+    Ok(())
+}
+
 fn load_settings() ->Result<Ntfy, String> {
     //get settings
-    let settings = Settings::new().unwrap_or_else(|err| {
-        log::error!("Failed to load settings: {}", err);
-        panic!("Failed to load settings: {}", err);
-    });
-    if let Some(notifications) = settings.notifications {
-        if let Some(ntfy_config) = notifications.ntfy {
-            return Ok(ntfy_config.clone());
+    if let Some(notifications) = &current_settings().notifications {
+        if let Some(ntfy_config) = &notifications.ntfy {
+            Ok(ntfy_config.clone())
         } else {
-            return Err("No Ntfy Config Found".to_string());
+            Err("No Ntfy Config Found".to_string())
         }
     } else {
-        return Err("No Notifications Config Found".to_string());
+        Err("No Notifications Config Found".to_string())
     }
 
 }
 
 pub async fn send_notification(workload: &Workload) -> Result<(), NtfyError> {
+    let Some(route) = route_for(workload) else {
+        return Ok(());
+    };
     //get settings
     match load_settings() {
         Ok(settings) => {
-            let url = settings.url;
-            let topic = settings.topic;
-            let token = settings.token;
-
-            let mut dispatcher = dispatcher::builder(&url)
-                .credentials(Auth::credentials("", &token)) // Add optional credentials
-                .build_blocking();
+            let url = settings.url.clone();
+            let topic = route.topic.unwrap_or_else(|| settings.topic.clone());
+            let mut dispatcher = dispatcher_builder(&settings).build_blocking();
 
             //let action = Action::new(
             //    ActionType::Http,
@@ -95,22 +292,28 @@ pub async fn send_notification(workload: &Workload) -> Result<(), NtfyError> {
             //);
 
             //make message for payload about new container update
-            let message = format!(
-                "Update Available: {} From {} to {}",
-                workload.name, workload.current_version, workload.latest_version
+            let message = with_changelog(
+                format!(
+                    "Update Available: {} From {} to {}",
+                    workload.name, workload.current_version, workload.latest_version
+                ),
+                workload,
             );
 
             let payload = Payload::new(&topic)
                 .message(message) // Add optional message
                 .title(&workload.name) // Add optiona title
                 .tags(["Update"]) // Add optional tags
-                .priority(Priority::High) // Edit priority
+                .priority(route.priority.unwrap_or(Priority::High)) // Edit priority
                 //.actions([action]) // Add optional actions
                 //.click(Url::parse("https://example.com")?) // Add optional clickable url
                 //.attach(Url::parse("https://example.com/file.jpg")?) // Add optional url attachment
                 //.delay(Local::now() + Duration::minutes(1)) // Add optional delay
                 .markdown(true); // Use markdown
 
+            if held_for_quiet_hours(&payload) {
+                return Ok(());
+            }
             match dispatcher?.send(&payload) {
                 Ok(_) => log::info!("Payload sent successfully."),
                 Err(e) => log::error!("Failed to send payload: {}", e),
@@ -127,3 +330,105 @@ pub async fn send_notification(workload: &Workload) -> Result<(), NtfyError> {
 
 
 }
+
+pub async fn notify_failure(
+    alerts: &FailureAlerts,
+    kind: FailureKind,
+    subject: &str,
+    message: &str,
+) -> Result<(), NtfyError> {
+    match load_settings() {
+        Ok(settings) => {
+            let topic = alerts.topic.clone().unwrap_or_else(|| settings.topic.clone());
+            let dispatcher = dispatcher_builder(&settings).build_blocking()?;
+
+            let payload = Payload::new(&topic)
+                .message(message)
+                .title(format!("Slackwatch {} failure: {}", kind, subject))
+                .tags(["warning"])
+                .priority(alerts.priority.unwrap_or(Priority::High))
+                .markdown(true);
+
+            if held_for_quiet_hours(&payload) {
+                return Ok(());
+            }
+            match dispatcher.send(&payload) {
+                Ok(_) => log::info!("Failure notification sent."),
+                Err(e) => log::error!("Failed to send failure notification: {}", e),
+            }
+            Ok(())
+        }
+        Err(e) => {
+            log::info!("Failed to load settings: {}", e);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Notifications, QuietHours, TimeWindow};
+
+    fn at(value: &str) -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&chrono::Utc)
+    }
+
+    #[test]
+    fn test_hold_until_quiet_hours_end() {
+        let settings = Settings {
+            notifications: Some(Notifications {
+                ntfy: None,
+                rules: Vec::new(),
+                failures: FailureAlerts::default(),
+                quiet_hours: Some(QuietHours {
+                    window: TimeWindow {
+                        start: Some("22:00".to_string()),
+                        end: Some("07:00".to_string()),
+                        ..TimeWindow::default()
+                    },
+                    urgent_priority: Priority::High,
+                }),
+            }),
+            ..Settings::default()
+        };
+        let payload = Payload::new("updates").title("postgres").priority(Priority::Default);
+        let mut stored = Vec::new();
+        let store = |json: &str, until: &str| {
+            stored.push((json.to_string(), until.to_string()));
+            Ok(())
+        };
+        assert_eq!(hold_until(&settings, &payload, at("2024-01-06T23:00:00Z"), store), Some(at("2024-01-07T07:00:00Z")));
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].1, "2024-01-07T07:00:00+00:00");
+        let held: Payload = serde_json::from_str(&stored[0].0).unwrap();
+        assert_eq!(held.title.as_deref(), Some("postgres"));
+
+        let unreachable = |_: &str, _: &str| -> Result<(), String> { panic!("not held") };
+        assert_eq!(hold_until(&settings, &payload, at("2024-01-06T12:00:00Z"), unreachable), None);
+        let urgent = payload.clone().priority(Priority::High);
+        assert_eq!(hold_until(&settings, &urgent, at("2024-01-06T23:00:00Z"), unreachable), None);
+        // Sent right away when it cannot be stored
+        let failing = |_: &str, _: &str| Err("database is locked".to_string());
+        assert_eq!(hold_until(&settings, &payload, at("2024-01-06T23:00:00Z"), failing), None);
+    }
+
+    #[test]
+    fn test_deliver_keeps_failed_notifications() {
+        let json = |title: &str| serde_json::to_string(&Payload::new("updates").title(title)).unwrap();
+        let held = vec![
+            (1, json("postgres"), 0),
+            (2, "{".to_string(), 0),
+            (3, json("redis"), 0),
+            (4, json("redis"), MAX_SEND_ATTEMPTS - 1),
+        ];
+        let delivered = deliver(held, |payload| match payload.title.as_deref() {
+            Some("redis") => Err("connection refused".to_string()),
+            _ => Ok(()),
+        });
+        assert_eq!(delivered[0], (1, Delivery::Sent));
+        assert!(matches!(delivered[1], (2, Delivery::Unreadable(_))));
+        assert_eq!(delivered[2], (3, Delivery::Failed("connection refused".to_string())));
+        assert_eq!(delivered[3], (4, Delivery::GaveUp("connection refused".to_string())));
+    }
+}
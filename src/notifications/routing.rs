@@ -0,0 +1,156 @@
+use crate::config::{current_settings, NotificationRule};
+use crate::models::models::{UpdateLevel, Workload};
+use ntfy::Priority;
+
+// Where a notification should be delivered once the routing rules have been applied.
+// `topic` and `priority` fall back to the notifier defaults when unset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub notifier: String,
+    pub topic: Option<String>,
+    pub priority: Option<Priority>,
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Route {
+            notifier: "ntfy".to_string(),
+            topic: None,
+            priority: None,
+        }
+    }
+}
+
+pub fn load_rules() -> Vec<NotificationRule> {
+    current_settings()
+        .notifications
+        .as_ref()
+        .map(|notifications| notifications.rules.clone())
+        .unwrap_or_default()
+}
+
+/// Find the route for a workload. The first matching rule wins, and `None` means
+/// the matching rule silences the notification. Without a match the default route is used.
+pub fn resolve_route(
+    rules: &[NotificationRule],
+    workload: &Workload,
+    level: Option<UpdateLevel>,
+) -> Option<Route> {
+    let Some(rule) = rules.iter().find(|rule| rule_matches(rule, workload, level)) else {
+        return Some(Route::default());
+    };
+    log::info!("Notification rule {:?} matched workload {}", rule, workload.name);
+    if rule.silence {
+        return None;
+    }
+    Some(Route {
+        notifier: rule.notifier.clone(),
+        topic: rule.topic.clone(),
+        priority: rule.priority,
+    })
+}
+
+fn rule_matches(rule: &NotificationRule, workload: &Workload, level: Option<UpdateLevel>) -> bool {
+    if let Some(namespace) = &rule.namespace {
+        if !glob_match(namespace, &workload.namespace) {
+            return false;
+        }
+    }
+    if let Some(name) = &rule.name {
+        if !glob_match(name, &workload.name) {
+            return false;
+        }
+    }
+    if let Some(repo) = &rule.git_ops_repo {
+        if workload.git_ops_repo.as_deref() != Some(repo.as_str()) {
+            return false;
+        }
+    }
+    if let Some(rule_level) = rule.update_level {
+        if level != Some(rule_level) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Shell style glob supporting `*` (any run of characters) and `?` (a single character).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::models::UpdateStatus;
+
+    fn workload(namespace: &str, name: &str) -> Workload {
+        Workload {
+            git_ops_repo: Some("fleet".to_string()),
+            update_available: UpdateStatus::Available,
+            namespace: namespace.to_string(),
+            ..Workload::for_test(name, &format!("{}:1.0.0", name), "1.0.0", "2.0.0")
+        }
+    }
+
+    fn rule(toml: &str) -> NotificationRule {
+        config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("prod-*", "prod-eu"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("d?v", "dev"));
+        assert!(glob_match("*-db-*", "prod-db-primary"));
+        assert!(!glob_match("prod-*", "dev-eu"));
+        assert!(!glob_match("dev", "dev2"));
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let rules = vec![
+            rule("namespace = \"prod-*\"\nupdate_level = \"major\"\npriority = \"high\"\ntopic = \"prod\""),
+            rule("namespace = \"dev\"\nupdate_level = \"patch\"\nsilence = true"),
+        ];
+
+        let route = resolve_route(&rules, &workload("prod-eu", "postgres"), Some(UpdateLevel::Major));
+        assert_eq!(
+            route,
+            Some(Route {
+                notifier: "ntfy".to_string(),
+                topic: Some("prod".to_string()),
+                priority: Some(Priority::High),
+            })
+        );
+        assert_eq!(resolve_route(&rules, &workload("dev", "postgres"), Some(UpdateLevel::Patch)), None);
+        assert_eq!(
+            resolve_route(&rules, &workload("dev", "postgres"), Some(UpdateLevel::Minor)),
+            Some(Route::default())
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use crate::config::{RegistryConfig, SecretSource};
+use crate::metrics::{REGISTRY_ERRORS, REGISTRY_REQUESTS};
+use crate::models::models::ImageTag;
+use crate::repocheck::ratelimit;
+use crate::repocheck::repocheck::{next_link, normalize_registry, TagList};
+use crate::secrets::resolve;
+use chrono::{SecondsFormat, TimeZone, Utc};
+use oci_distribution::Reference;
+use reqwest::header::{HeaderMap, ACCEPT, ETAG, IF_NONE_MATCH, LINK, USER_AGENT};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+// Registry specific APIs that list tags together with their push dates, selected by
+// registry host. Registries without one use the plain OCI tag list.
+
+const PAGE_SIZE: usize = 100;
+const MAX_PAGES: usize = 20;
+
+static API_TOKENS: LazyLock<Mutex<HashMap<String, SecretSource>>> = LazyLock::new(Default::default);
+
+/// Remember where API tokens are read from, replacing any previous ones.
+pub fn configure(registries: &[RegistryConfig]) {
+    *API_TOKENS.lock().unwrap() = registries
+        .iter()
+        .map(|registry| (normalize_registry(&registry.host).to_string(), registry.api_token()))
+        .filter(|(_, token)| token.is_set())
+        .collect();
+}
+
+fn api_token(registry: &str) -> Option<String> {
+    let source = API_TOKENS.lock().unwrap().get(registry).cloned()?;
+    resolve(&source)
+}
+
+/// Tags with push dates from the registry's own API, newest first. The first page is
+/// requested with If-None-Match when an ETag is known. `None` when the registry has no
+/// supported API or it cannot be used without credentials.
+pub async fn fetch_tags(reference: &Reference, etag: Option<&str>) -> Option<Result<TagList, reqwest::Error>> {
+    let registry = normalize_registry(reference.registry());
+    let result = match registry {
+        "docker.io" => docker_hub(reference.repository(), etag).await,
+        "ghcr.io" => ghcr(reference.repository(), &api_token(registry)?, etag).await,
+        "quay.io" => quay(reference.repository(), etag).await,
+        _ => return None,
+    };
+    Some(result.map(|tag_list| match tag_list {
+        TagList::Fetched { mut tags, etag } => {
+            sort_newest_first(&mut tags);
+            TagList::Fetched { tags, etag }
+        }
+        TagList::NotModified => TagList::NotModified,
+    }))
+}
+
+// An ETag is only kept for lists that fit on one page, as it says nothing about later pages
+fn fetched(tags: Vec<ImageTag>, etag: Option<String>, pages: usize) -> TagList {
+    TagList::Fetched {
+        tags,
+        etag: etag.filter(|_| pages == 1),
+    }
+}
+
+/// Dated tags first, newest to oldest, then undated tags by name.
+pub fn sort_newest_first(tags: &mut [ImageTag]) {
+    tags.sort_by(|a, b| b.published.cmp(&a.published).then_with(|| a.name.cmp(&b.name)));
+}
+
+// A page of an API, None when the registry answered that it is unchanged since `etag`
+async fn get_json<T: DeserializeOwned>(
+    registry: &str,
+    url: &str,
+    token: Option<&str>,
+    etag: Option<&str>,
+) -> Result<Option<(T, HeaderMap)>, reqwest::Error> {
+    ratelimit::acquire(registry).await;
+    REGISTRY_REQUESTS.with_label_values(&[registry]).inc();
+    let mut request = reqwest::Client::new()
+        .get(url)
+        .header(USER_AGENT, "slackwatch")
+        .header(ACCEPT, "application/json");
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    let result = async {
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        let headers = response.headers().clone();
+        Ok(Some((response.json().await?, headers)))
+    }
+    .await;
+    if result.is_err() {
+        REGISTRY_ERRORS.with_label_values(&[registry]).inc();
+    }
+    result
+}
+
+fn etag_of(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+fn normalize_date(date: &str) -> Option<String> {
+    chrono::DateTime::parse_from_rfc3339(date)
+        .ok()
+        .map(|date| date.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+#[derive(Deserialize)]
+struct HubPage {
+    next: Option<String>,
+    results: Vec<HubTag>,
+}
+
+#[derive(Deserialize)]
+struct HubTag {
+    name: String,
+    tag_last_pushed: Option<String>,
+    last_updated: Option<String>,
+}
+
+// Docker Hub v2 repositories API, official images live under `library/`
+async fn docker_hub(repository: &str, etag: Option<&str>) -> Result<TagList, reqwest::Error> {
+    let mut url = Some(format!(
+        "https://hub.docker.com/v2/repositories/{}/tags?page_size={}&ordering=last_updated",
+        repository, PAGE_SIZE
+    ));
+    let mut tags = Vec::new();
+    let mut first_etag = None;
+    let mut pages = 0;
+    for _ in 0..MAX_PAGES {
+        let Some(page_url) = url.take() else { break };
+        let etag = if pages == 0 { etag } else { None };
+        let Some((page, headers)): Option<(HubPage, _)> = get_json("docker.io", &page_url, None, etag).await? else {
+            return Ok(TagList::NotModified);
+        };
+        if pages == 0 {
+            first_etag = etag_of(&headers);
+        }
+        pages += 1;
+        tags.extend(page.results.into_iter().map(|tag| ImageTag {
+            published: tag
+                .tag_last_pushed
+                .or(tag.last_updated)
+                .as_deref()
+                .and_then(normalize_date),
+            name: tag.name,
+        }));
+        url = page.next;
+    }
+    Ok(fetched(tags, first_etag, pages))
+}
+
+#[derive(Deserialize)]
+struct GhcrVersion {
+    created_at: String,
+    metadata: GhcrMetadata,
+}
+
+#[derive(Deserialize)]
+struct GhcrMetadata {
+    container: GhcrContainer,
+}
+
+#[derive(Deserialize)]
+struct GhcrContainer {
+    tags: Vec<String>,
+}
+
+// GitHub packages API, needs a token with read:packages even for public images
+async fn ghcr(repository: &str, token: &str, etag: Option<&str>) -> Result<TagList, reqwest::Error> {
+    let (owner, package) = repository.split_once('/').unwrap_or((repository, repository));
+    let package = package.replace('/', "%2F");
+    // Packages belong to either an organization or a user, try both
+    let mut result = Ok(fetched(Vec::new(), None, 0));
+    for kind in ["orgs", "users"] {
+        let url = format!(
+            "https://api.github.com/{}/{}/packages/container/{}/versions?per_page={}",
+            kind, owner, package, PAGE_SIZE
+        );
+        result = ghcr_versions(url, token, etag).await;
+        match &result {
+            Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => continue,
+            _ => break,
+        }
+    }
+    result
+}
+
+async fn ghcr_versions(url: String, token: &str, etag: Option<&str>) -> Result<TagList, reqwest::Error> {
+    let mut url = Some(url);
+    let mut tags = Vec::new();
+    let mut first_etag = None;
+    let mut pages = 0;
+    for _ in 0..MAX_PAGES {
+        let Some(page_url) = url.take() else { break };
+        let etag = if pages == 0 { etag } else { None };
+        let Some((versions, headers)): Option<(Vec<GhcrVersion>, _)> =
+            get_json("ghcr.io", &page_url, Some(token), etag).await?
+        else {
+            return Ok(TagList::NotModified);
+        };
+        if pages == 0 {
+            first_etag = etag_of(&headers);
+        }
+        pages += 1;
+        for version in versions {
+            let published = normalize_date(&version.created_at);
+            tags.extend(version.metadata.container.tags.into_iter().map(|name| ImageTag {
+                name,
+                published: published.clone(),
+            }));
+        }
+        url = headers
+            .get(LINK)
+            .and_then(|link| link.to_str().ok())
+            .and_then(next_link);
+    }
+    Ok(fetched(tags, first_etag, pages))
+}
+
+#[derive(Deserialize)]
+struct QuayPage {
+    tags: Vec<QuayTag>,
+    has_additional: bool,
+}
+
+#[derive(Deserialize)]
+struct QuayTag {
+    name: String,
+    start_ts: Option<i64>,
+}
+
+// Quay API, public repositories can be listed anonymously
+async fn quay(repository: &str, etag: Option<&str>) -> Result<TagList, reqwest::Error> {
+    let mut tags = Vec::new();
+    let mut first_etag = None;
+    let mut pages = 0;
+    for page in 1..=MAX_PAGES {
+        let url = format!(
+            "https://quay.io/api/v1/repository/{}/tag/?limit={}&page={}&onlyActiveTags=true",
+            repository, PAGE_SIZE, page
+        );
+        let etag = if page == 1 { etag } else { None };
+        let Some((response, headers)): Option<(QuayPage, _)> = get_json("quay.io", &url, None, etag).await? else {
+            return Ok(TagList::NotModified);
+        };
+        if page == 1 {
+            first_etag = etag_of(&headers);
+        }
+        pages = page;
+        tags.extend(response.tags.into_iter().map(|tag| ImageTag {
+            name: tag.name,
+            published: tag
+                .start_ts
+                .and_then(|ts| Utc.timestamp_opt(ts, 0).single())
+                .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        }));
+        if !response.has_additional {
+            break;
+        }
+    }
+    Ok(fetched(tags, first_etag, pages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort_newest_first() {
+        let tag = |name: &str, published: Option<&str>| ImageTag {
+            name: name.to_string(),
+            published: published.and_then(normalize_date),
+        };
+        let mut tags = vec![
+            tag("latest", None),
+            tag("16.1", Some("2024-01-01T00:00:00Z")),
+            tag("16.2", Some("2024-02-01T10:00:00.123456Z")),
+            tag("15.6", Some("2024-02-01T09:00:00+00:00")),
+        ];
+        sort_newest_first(&mut tags);
+        let names: Vec<&str> = tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert_eq!(names, vec!["16.2", "15.6", "16.1", "latest"]);
+    }
+}
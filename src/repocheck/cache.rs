@@ -0,0 +1,65 @@
+use crate::config::RegistryConfig;
+use crate::repocheck::repocheck::normalize_registry;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+#[derive(Default)]
+struct Ttls {
+    default: u64,
+    registries: HashMap<String, u64>,
+}
+
+static TTLS: LazyLock<Mutex<Ttls>> = LazyLock::new(Default::default);
+
+/// Apply the tag cache TTLs from the configuration, replacing any previous ones.
+pub fn configure(default_ttl_seconds: u64, registries: &[RegistryConfig]) {
+    let mut ttls = TTLS.lock().unwrap();
+    ttls.default = default_ttl_seconds;
+    ttls.registries = registries
+        .iter()
+        .filter_map(|registry| {
+            registry
+                .cache_ttl_seconds
+                .map(|ttl| (normalize_registry(&registry.host).to_string(), ttl))
+        })
+        .collect();
+}
+
+fn ttl(registry: &str) -> u64 {
+    let ttls = TTLS.lock().unwrap();
+    ttls.registries
+        .get(normalize_registry(registry))
+        .copied()
+        .unwrap_or(ttls.default)
+}
+
+/// Whether a tag list fetched at `fetched_at` (RFC 3339) can still be used without asking the registry.
+pub fn is_fresh(registry: &str, fetched_at: &str) -> bool {
+    let Ok(fetched_at) = chrono::DateTime::parse_from_rfc3339(fetched_at) else {
+        return false;
+    };
+    let age = chrono::Utc::now().signed_duration_since(fetched_at);
+    age.num_seconds() >= 0 && (age.num_seconds() as u64) < ttl(registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ttl_per_registry() {
+        configure(
+            3600,
+            &[RegistryConfig {
+                host: "ghcr.io".to_string(),
+                requests_per_minute: None,
+                cache_ttl_seconds: Some(0),
+                ..RegistryConfig::default()
+            }],
+        );
+        let ten_minutes_ago = (chrono::Utc::now() - chrono::Duration::minutes(10)).to_rfc3339();
+        assert!(is_fresh("docker.io", &ten_minutes_ago));
+        assert!(!is_fresh("ghcr.io", &ten_minutes_ago));
+        assert!(!is_fresh("docker.io", "not a date"));
+    }
+}
@@ -0,0 +1,35 @@
+use crate::config::{RegistryConfig, SecretSource};
+use crate::repocheck::repocheck::normalize_registry;
+use crate::secrets::resolve;
+use oci_distribution::secrets::RegistryAuth;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+// Pull credentials by registry host, used for tag lists, manifests and image configs
+
+static CREDENTIALS: LazyLock<Mutex<HashMap<String, (String, SecretSource)>>> = LazyLock::new(Default::default);
+
+/// Remember the pull credentials of each registry, replacing any previous ones.
+pub fn configure(registries: &[RegistryConfig]) {
+    *CREDENTIALS.lock().unwrap() = registries
+        .iter()
+        .filter_map(|registry| {
+            let username = registry.username.clone()?;
+            Some((normalize_registry(&registry.host).to_string(), (username, registry.password())))
+        })
+        .collect();
+}
+
+/// Credentials for a registry, anonymous when none are configured or the password cannot be read.
+pub fn registry_auth(registry: &str) -> RegistryAuth {
+    let Some((username, password)) = CREDENTIALS.lock().unwrap().get(normalize_registry(registry)).cloned() else {
+        return RegistryAuth::Anonymous;
+    };
+    match resolve(&password) {
+        Some(password) => RegistryAuth::Basic(username, password),
+        None => {
+            log::warn!("Password for {} is not available, pulling anonymously", registry);
+            RegistryAuth::Anonymous
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::config::{MirrorConfig, MirrorQuery};
+use std::sync::{LazyLock, Mutex};
+
+static MIRRORS: LazyLock<Mutex<Vec<MirrorConfig>>> = LazyLock::new(Default::default);
+
+/// Apply the mirror mappings from the configuration, replacing any previous ones.
+pub fn configure(mirrors: &[MirrorConfig]) {
+    *MIRRORS.lock().unwrap() = mirrors.to_vec();
+}
+
+// The mirror whose prefix the image starts with, and the rest of the image after it
+fn find_mirror(image: &str) -> Option<(MirrorConfig, String)> {
+    let mirrors = MIRRORS.lock().unwrap();
+    mirrors.iter().find_map(|mirror| {
+        let rest = image.strip_prefix(mirror.prefix.trim_end_matches('/'))?;
+        let rest = rest.strip_prefix('/')?;
+        Some((mirror.clone(), rest.to_string()))
+    })
+}
+
+/// The image as published upstream, e.g. `harbor.local/dockerhub/library/postgres:16`
+/// becomes `docker.io/library/postgres:16`. Images not behind a mirror are returned as is.
+pub fn upstream_image(image: &str) -> String {
+    match find_mirror(image) {
+        Some((mirror, rest)) => format!("{}/{}", mirror.upstream.trim_end_matches('/'), rest),
+        None => image.to_string(),
+    }
+}
+
+/// The image to list tags and read manifests from, the upstream unless the mirror is configured
+/// to be queried directly.
+pub fn query_image(image: &str) -> String {
+    match find_mirror(image) {
+        Some((mirror, _)) if mirror.query == MirrorQuery::Mirror => image.to_string(),
+        _ => upstream_image(image),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_mapping() {
+        configure(&[
+            MirrorConfig {
+                prefix: "harbor.local/dockerhub".to_string(),
+                upstream: "docker.io".to_string(),
+                query: MirrorQuery::Upstream,
+            },
+            MirrorConfig {
+                prefix: "harbor.local/ghcr/".to_string(),
+                upstream: "ghcr.io".to_string(),
+                query: MirrorQuery::Mirror,
+            },
+        ]);
+        assert_eq!(
+            query_image("harbor.local/dockerhub/library/postgres:16.1"),
+            "docker.io/library/postgres:16.1"
+        );
+        assert_eq!(
+            query_image("harbor.local/ghcr/slackspace-io/slackwatch:0.3.10"),
+            "harbor.local/ghcr/slackspace-io/slackwatch:0.3.10"
+        );
+        assert_eq!(
+            upstream_image("harbor.local/ghcr/slackspace-io/slackwatch:0.3.10"),
+            "ghcr.io/slackspace-io/slackwatch:0.3.10"
+        );
+        assert_eq!(query_image("harbor.local/dockerhubx/postgres"), "harbor.local/dockerhubx/postgres");
+        assert_eq!(query_image("postgres:16.1"), "postgres:16.1");
+    }
+}
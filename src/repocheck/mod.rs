@@ -1 +1,7 @@
+#[allow(clippy::module_inception)]
 pub mod repocheck;
+pub mod backends;
+pub mod cache;
+pub mod credentials;
+pub mod mirrors;
+pub mod ratelimit;
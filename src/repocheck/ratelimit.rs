@@ -0,0 +1,73 @@
+use crate::config::RegistryConfig;
+use crate::repocheck::repocheck::normalize_registry;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Minimum spacing between requests and the next free slot for each rate limited registry
+struct Limit {
+    interval: Duration,
+    next: Instant,
+}
+
+static LIMITS: LazyLock<Mutex<HashMap<String, Limit>>> = LazyLock::new(Default::default);
+
+/// Apply the rate limits from the registry configuration, replacing any previous ones.
+pub fn configure(registries: &[RegistryConfig]) {
+    let mut limits = LIMITS.lock().unwrap();
+    limits.clear();
+    for registry in registries {
+        if let Some(per_minute) = registry.requests_per_minute.filter(|n| *n > 0) {
+            limits.insert(
+                normalize_registry(&registry.host).to_string(),
+                Limit {
+                    interval: Duration::from_secs(60) / per_minute,
+                    next: Instant::now(),
+                },
+            );
+        }
+    }
+}
+
+/// Wait until a request to `registry` is allowed. Registries without a limit return at once.
+pub async fn acquire(registry: &str) {
+    let wait = {
+        let mut limits = LIMITS.lock().unwrap();
+        let Some(limit) = limits.get_mut(normalize_registry(registry)) else {
+            return;
+        };
+        let now = Instant::now();
+        let slot = limit.next.max(now);
+        limit.next = slot + limit.interval;
+        slot - now
+    };
+    if !wait.is_zero() {
+        log::debug!("Rate limiting {} for {:?}", registry, wait);
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_are_spaced_by_limit() {
+        configure(&[RegistryConfig {
+            host: "index.docker.io".to_string(),
+            requests_per_minute: Some(60),
+            cache_ttl_seconds: None,
+            ..RegistryConfig::default()
+        }]);
+        let started = Instant::now();
+        for _ in 0..3 {
+            acquire("docker.io").await;
+        }
+        assert_eq!(started.elapsed().as_secs(), 2);
+
+        let started = Instant::now();
+        acquire("ghcr.io").await;
+        assert!(started.elapsed().is_zero());
+    }
+}
@@ -1,6 +1,19 @@
 use oci_distribution::client::{Client, ClientConfig};
-use oci_distribution::secrets::RegistryAuth;
-use oci_distribution::Reference;
+use oci_distribution::errors::{OciDistributionError, OciErrorCode};
+use oci_distribution::manifest::OciManifest;
+use oci_distribution::{Reference, RegistryOperation};
+use reqwest::header::{HeaderMap, HeaderName, ETAG, IF_NONE_MATCH, LINK};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::config::Settings;
+use crate::database::client::{
+    insert_cached_config, insert_cached_tags, return_cached_config, return_cached_tags, touch_cached_tags,
+};
+use crate::metrics::{REGISTRY_ERRORS, REGISTRY_REQUESTS};
+use crate::models::models::{CachedTags, ImageMetadata, ImageTag};
+use crate::repocheck::credentials::registry_auth;
+use crate::repocheck::{backends, cache, credentials, mirrors, ratelimit};
 
 //pub async fn test_call() -> Result<Vec<String>, Box<dyn std::error::Error>> {
 //    let reference = Reference::try_from("binwiederhier/ntfy")?;
@@ -21,55 +34,457 @@ use oci_distribution::Reference;
 //    Ok(tags)
 //}
 
-pub async fn get_tags_for_image(image: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let reference = Reference::try_from(image)?;
-    let auth = RegistryAuth::Anonymous;
-    let config = ClientConfig::default();
-    let mut client = Client::new(config);
-    let max_tags = Some(1500);
-    log::info!("Fetching tags for image: {:?}", reference.tag());
+const PAGE_SIZE: usize = 1000;
+const MAX_PAGES: usize = 20;
 
+// How long the config of a tag is reused, tags are rarely pushed again
+const CONFIG_CACHE_DAYS: i64 = 7;
+
+#[derive(Deserialize)]
+struct TagPage {
+    tags: Option<Vec<String>>,
+}
+
+/// A tag list fetched from a registry, or confirmation that the cached one is unchanged.
+pub enum TagList {
+    NotModified,
+    Fetched { tags: Vec<ImageTag>, etag: Option<String> },
+}
+
+/// Apply the registry settings used when fetching tags.
+pub fn configure(settings: &Settings) {
+    ratelimit::configure(&settings.registries);
+    backends::configure(&settings.registries);
+    credentials::configure(&settings.registries);
+    mirrors::configure(&settings.mirrors);
+    cache::configure(settings.system.tag_cache_ttl_seconds, &settings.registries);
+}
+
+/// Docker Hub references parse as `docker.io` but are commonly configured by their API host
+pub fn normalize_registry(host: &str) -> &str {
+    match host {
+        "index.docker.io" | "registry-1.docker.io" => "docker.io",
+        host => host,
+    }
+}
+
+/// All tags of an image repository, newest first where the registry reports push dates.
+/// Tag lists are cached in the database and reused until their TTL expires, after which the
+/// registry is asked again with the cached ETag. `bypass_cache` always fetches the full list.
+pub async fn get_tags_for_image(
+    image: &str,
+    bypass_cache: bool,
+) -> Result<Vec<ImageTag>, Box<dyn std::error::Error>> {
+    let reference = Reference::try_from(mirrors::query_image(image))?;
+    let repository = format!("{}/{}", reference.registry(), reference.repository());
+    let cached = if bypass_cache {
+        None
+    } else {
+        return_cached_tags(&repository).unwrap_or_else(|e| {
+            log::warn!("Failed to read tag cache for {}: {}", repository, e);
+            None
+        })
+    };
+    if let Some(cached) = &cached {
+        if cache::is_fresh(reference.registry(), &cached.fetched_at) {
+            log::info!("Using {} cached tags for {}", cached.tags.len(), repository);
+            return Ok(cached.tags.clone());
+        }
+    }
+
+    let etag = cached.as_ref().and_then(|cached| cached.etag.as_deref());
+    let tag_list = match backends::fetch_tags(&reference, etag).await {
+        Some(Ok(tag_list)) => tag_list,
+        Some(Err(e)) => {
+            log::warn!("Tag API for {} failed, using the registry tag list: {}", repository, e);
+            fetch_tags(&reference, etag).await?
+        }
+        None => fetch_tags(&reference, etag).await?,
+    };
+    let tags = settle_tag_list(
+        &repository,
+        cached,
+        tag_list,
+        || {
+            touch_cached_tags(&repository)
+                .unwrap_or_else(|e| log::warn!("Failed to refresh tag cache for {}: {}", repository, e))
+        },
+        |tags, etag| {
+            insert_cached_tags(&repository, tags, etag)
+                .unwrap_or_else(|e| log::warn!("Failed to cache tags for {}: {}", repository, e))
+        },
+    );
+    Ok(tags)
+}
+
+// The tags to use once the registry answered: the cached list, marked fresh again, when it is
+// unchanged, otherwise the fetched list, stored with its ETag.
+fn settle_tag_list(
+    repository: &str,
+    cached: Option<CachedTags>,
+    tag_list: TagList,
+    touch: impl FnOnce(),
+    store: impl FnOnce(&[ImageTag], Option<&str>),
+) -> Vec<ImageTag> {
+    match tag_list {
+        TagList::NotModified => {
+            log::info!("Tags for {} not modified since last fetch", repository);
+            touch();
+            cached.map(|cached| cached.tags).unwrap_or_default()
+        }
+        TagList::Fetched { tags, etag } => {
+            log::info!("Total number of tags collected for {}: {}", repository, tags.len());
+            store(&tags, etag.as_deref());
+            tags
+        }
+    }
+}
+
+// Walk the paginated tag list. The first page is requested with If-None-Match when an ETag is
+// known. An ETag is only kept for lists that fit on one page, as it says nothing about later pages.
+async fn fetch_tags(
+    reference: &Reference,
+    etag: Option<&str>,
+) -> Result<TagList, Box<dyn std::error::Error>> {
+    let client = Client::new(ClientConfig::default());
+    let token = client
+        .auth(reference, &registry_auth(reference.registry()), RegistryOperation::Pull)
+        .await
+        .inspect_err(|_| REGISTRY_ERRORS.with_label_values(&[reference.registry()]).inc())?;
+    let http = reqwest::Client::new();
+    let host = reference.resolve_registry();
+    let mut url = format!(
+        "https://{}/v2/{}/tags/list?n={}",
+        host,
+        reference.repository(),
+        PAGE_SIZE
+    );
     let mut all_tags = Vec::new();
-    let mut last_tag = reference.tag().map(|s| s.to_string());
-    let mut attempt_count = 0;
-    const MAX_ATTEMPTS: usize = 20;
-
-    loop {
-        // Prevent infinite loop by limiting the number of attempts
-        attempt_count += 1;
-        if attempt_count > MAX_ATTEMPTS {
-            log::warn!("Reached maximum number of attempts ({}) for fetching tags. Some tags might be missing.", MAX_ATTEMPTS);
-            break;
+    let mut first_etag = None;
+
+    for page in 0..MAX_PAGES {
+        log::info!("Fetching tags for {} from {}", reference.repository(), url);
+        ratelimit::acquire(reference.registry()).await;
+        REGISTRY_REQUESTS.with_label_values(&[reference.registry()]).inc();
+        let mut request = http.get(&url);
+        if let Some(token) = &token {
+            request = request.bearer_auth(token);
+        }
+        if let (0, Some(etag)) = (page, etag) {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = request
+            .send()
+            .await
+            .inspect_err(|_| REGISTRY_ERRORS.with_label_values(&[reference.registry()]).inc())?;
+
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(TagList::NotModified);
+        }
+        if !status.is_success() {
+            REGISTRY_ERRORS.with_label_values(&[reference.registry()]).inc();
+            let message = response.text().await.unwrap_or_default();
+            return Err(Box::new(OciDistributionError::ServerError {
+                code: status.as_u16(),
+                url,
+                message,
+            }));
+        }
+        if page == 0 {
+            first_etag = header_str(response.headers(), ETAG);
+        }
+        let next = header_str(response.headers(), LINK).and_then(|link| next_link(&link));
+        let tags: TagPage = response.json().await?;
+        all_tags.extend(tags.tags.unwrap_or_default().into_iter().map(|name| ImageTag {
+            name,
+            published: None,
+        }));
+
+        match next {
+            Some(next) if next.starts_with('/') => url = format!("https://{}{}", host, next),
+            Some(next) => url = next,
+            None => {
+                return Ok(TagList::Fetched {
+                    tags: all_tags,
+                    etag: if page == 0 { first_etag } else { None },
+                })
+            }
+        }
+    }
+    log::warn!("Reached maximum number of pages ({}) for fetching tags. Some tags might be missing.", MAX_PAGES);
+    Ok(TagList::Fetched {
+        tags: all_tags,
+        etag: None,
+    })
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+/// The target of the `rel="next"` entry of a Link header, as used by registries to paginate.
+pub fn next_link(link: &str) -> Option<String> {
+    link.split(',').find_map(|entry| {
+        let (target, params) = entry.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim().replace(' ', "") == "rel=\"next\"")
+            .then(|| target.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+    })
+}
+
+#[derive(Deserialize, Serialize)]
+struct ImageConfig {
+    created: Option<String>,
+    architecture: Option<String>,
+    config: Option<ContainerConfig>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ContainerConfig {
+    #[serde(rename = "Labels")]
+    labels: Option<HashMap<String, String>>,
+}
+
+fn tag_reference(image: &str, tag: &str) -> Result<Reference, String> {
+    let reference = Reference::try_from(mirrors::query_image(image)).map_err(|e| e.to_string())?;
+    Ok(Reference::with_tag(
+        reference.registry().to_string(),
+        reference.repository().to_string(),
+        tag.to_string(),
+    ))
+}
+
+/// Architectures `tag` of an image is published for, from its manifest list or OCI index,
+/// or the config of a single platform image.
+pub async fn get_tag_architectures(image: &str, tag: &str) -> Result<Vec<String>, String> {
+    let reference = tag_reference(image, tag)?;
+    let client = Client::new(ClientConfig::default());
+    let count_error = |e: OciDistributionError| {
+        REGISTRY_ERRORS.with_label_values(&[reference.registry()]).inc();
+        e.to_string()
+    };
+    ratelimit::acquire(reference.registry()).await;
+    REGISTRY_REQUESTS.with_label_values(&[reference.registry()]).inc();
+    let (manifest, _) = client
+        .pull_manifest(&reference, &registry_auth(reference.registry()))
+        .await
+        .map_err(count_error)?;
+    match manifest {
+        OciManifest::ImageIndex(index) => Ok(index
+            .manifests
+            .into_iter()
+            .filter_map(|entry| entry.platform)
+            // Attestation manifests are listed with an unknown platform
+            .filter(|platform| platform.architecture != "unknown")
+            .map(|platform| platform.architecture)
+            .collect()),
+        OciManifest::Image(manifest) => {
+            let mut config = Vec::new();
+            ratelimit::acquire(reference.registry()).await;
+            REGISTRY_REQUESTS.with_label_values(&[reference.registry()]).inc();
+            client
+                .pull_blob(&reference, &manifest.config, &mut config)
+                .await
+                .map_err(count_error)?;
+            let config: ImageConfig = serde_json::from_slice(&config).map_err(|e| e.to_string())?;
+            Ok(config.architecture.into_iter().collect())
         }
-        log::info!("Fetching tags with last tag: {:?}", last_tag);
-        let tags = client
-            .list_tags(&reference, &auth, max_tags, last_tag.as_deref())
-            .await?;
-
-        log::info!("Available tags for {}: {:?}", reference, tags.tags);
-        log::info!("Number of tags: {}", tags.tags.len());
-
-        // Add the tags to our collection
-        all_tags.extend(tags.tags.clone());
-
-        // If we got exactly 1000 tags, it might indicate we hit a limit
-        // Take the latest tag and rerun the query
-        if tags.tags.len() >= 1000 {
-            // Find the latest tag (alphabetically last)
-            if let Some(latest) = tags.tags.iter().max() {
-                last_tag = Some(latest.clone());
-                log::info!("Got 1000 results, continuing with last tag: {}", latest);
-            } else {
-                // This shouldn't happen as we just checked there are 1000 tags
-                break;
+    }
+}
+
+fn config_is_fresh(fetched_at: &str, now: chrono::DateTime<chrono::Utc>) -> bool {
+    chrono::DateTime::parse_from_rfc3339(fetched_at)
+        .is_ok_and(|fetched_at| now.signed_duration_since(fetched_at) < chrono::Duration::days(CONFIG_CACHE_DAYS))
+}
+
+// The config of a tag, cached in the database so every scan does not pull it again
+async fn get_tag_config(image: &str, tag: &str) -> Result<ImageConfig, String> {
+    let reference = tag_reference(image, tag)?;
+    let repository = format!("{}/{}", reference.registry(), reference.repository());
+    match return_cached_config(&repository, tag) {
+        Ok(Some((config, fetched_at))) if config_is_fresh(&fetched_at, chrono::Utc::now()) => {
+            if let Ok(config) = serde_json::from_str(&config) {
+                return Ok(config);
             }
-        } else {
-            // We got fewer than 1000 tags, we're done
-            break;
         }
+        Ok(_) => {}
+        Err(e) => log::warn!("Failed to read config cache for {}:{}: {}", repository, tag, e),
+    }
+
+    let client = Client::new(ClientConfig::default());
+    ratelimit::acquire(reference.registry()).await;
+    REGISTRY_REQUESTS.with_label_values(&[reference.registry()]).inc();
+    let (_, _, config) = client
+        .pull_manifest_and_config(&reference, &registry_auth(reference.registry()))
+        .await
+        .map_err(|e| {
+            REGISTRY_ERRORS.with_label_values(&[reference.registry()]).inc();
+            e.to_string()
+        })?;
+    let config: ImageConfig = serde_json::from_str(&config).map_err(|e| e.to_string())?;
+    // Only the fields read are kept, not the layer history
+    let stored = serde_json::to_string(&config).map_err(|e| e.to_string())?;
+    insert_cached_config(&repository, tag, &stored)
+        .unwrap_or_else(|e| log::warn!("Failed to cache config for {}:{}: {}", repository, tag, e));
+    Ok(config)
+}
+
+/// When `tag` of an image was built, from the `created` field of its config blob.
+pub async fn get_tag_created(image: &str, tag: &str) -> Result<Option<String>, String> {
+    Ok(get_tag_config(image, tag).await?.created)
+}
+
+/// The standard OCI labels of `tag` of an image.
+pub async fn get_image_metadata(image: &str, tag: &str) -> Result<ImageMetadata, String> {
+    let config = get_tag_config(image, tag).await?;
+    let labels = config.config.and_then(|config| config.labels).unwrap_or_default();
+    Ok(metadata_from_labels(labels, config.created))
+}
+
+fn metadata_from_labels(mut labels: HashMap<String, String>, created: Option<String>) -> ImageMetadata {
+    let mut label = |name: &str| {
+        labels
+            .remove(&format!("org.opencontainers.image.{}", name))
+            .filter(|value| !value.trim().is_empty())
+    };
+    ImageMetadata {
+        version: label("version"),
+        source: label("source"),
+        revision: label("revision"),
+        created: label("created").or(created),
+        licenses: label("licenses"),
+    }
+}
+
+/// The registry and repository of an image without its tag or digest, e.g. `docker.io/library/postgres`.
+/// Workloads sharing a repository share its tag list.
+pub fn repository_of(image: &str) -> Result<String, String> {
+    let reference = Reference::try_from(mirrors::query_image(image)).map_err(|e| e.to_string())?;
+    Ok(format!("{}/{}", reference.registry(), reference.repository()))
+}
+
+/// Like `repository_of`, but always the upstream repository even when tags are listed from a mirror.
+pub fn upstream_repository_of(image: &str) -> Result<String, String> {
+    let reference = Reference::try_from(mirrors::upstream_image(image)).map_err(|e| e.to_string())?;
+    Ok(format!("{}/{}", reference.registry(), reference.repository()))
+}
+
+/// The image without its tag or digest. A port in the registry host is not mistaken for a tag.
+pub fn strip_tag(image: &str) -> &str {
+    let image = image.split_once('@').map_or(image, |(name, _)| name);
+    match image.rfind(':') {
+        Some(colon) if !image[colon..].contains('/') => &image[..colon],
+        _ => image,
+    }
+}
+
+/// Whether a registry error was caused by missing or rejected credentials.
+pub fn is_auth_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    match error.downcast_ref::<OciDistributionError>() {
+        Some(OciDistributionError::AuthenticationFailure(_))
+        | Some(OciDistributionError::UnauthorizedError { .. })
+        | Some(OciDistributionError::RegistryTokenDecodeError(_)) => true,
+        Some(OciDistributionError::ServerError { code, .. }) => *code == 401 || *code == 403,
+        Some(OciDistributionError::RegistryError { envelope, .. }) => envelope
+            .errors
+            .iter()
+            .any(|e| matches!(e.code, OciErrorCode::Unauthorized | OciErrorCode::Denied)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_link() {
+        assert_eq!(
+            next_link("</v2/library/postgres/tags/list?last=16.1&n=1000>; rel=\"next\""),
+            Some("/v2/library/postgres/tags/list?last=16.1&n=1000".to_string())
+        );
+        assert_eq!(next_link("<https://example.com/page1>; rel=\"prev\""), None);
+    }
+
+    #[test]
+    fn test_not_modified_reuses_cached_tags() {
+        let tag = |name: &str| ImageTag { name: name.to_string(), published: None };
+        let cached = CachedTags {
+            repository: "docker.io/library/postgres".to_string(),
+            tags: vec![tag("16.1"), tag("16.2")],
+            etag: Some("\"abc\"".to_string()),
+            fetched_at: "2024-01-01T00:00:00+00:00".to_string(),
+        };
+
+        let repository = "docker.io/library/postgres";
+        let mut touched = false;
+        let tags = settle_tag_list(
+            repository,
+            Some(cached.clone()),
+            TagList::NotModified,
+            || touched = true,
+            |_, _| panic!("an unchanged list is not stored again"),
+        );
+        assert!(touched);
+        assert_eq!(tags, cached.tags);
+
+        let mut stored = None;
+        let fetched = TagList::Fetched {
+            tags: vec![tag("16.3")],
+            etag: Some("\"def\"".to_string()),
+        };
+        let tags = settle_tag_list(
+            repository,
+            Some(cached),
+            fetched,
+            || panic!("a changed list is not touched"),
+            |tags, etag| stored = Some((tags.to_vec(), etag.map(String::from))),
+        );
+        assert_eq!(tags, vec![tag("16.3")]);
+        assert_eq!(stored, Some((vec![tag("16.3")], Some("\"def\"".to_string()))));
     }
 
-    log::info!("Total number of tags collected: {}", all_tags.len());
+    #[test]
+    fn test_metadata_from_labels() {
+        let labels = HashMap::from([
+            ("org.opencontainers.image.version".to_string(), "v1.106.4".to_string()),
+            ("org.opencontainers.image.source".to_string(), "https://github.com/immich-app/immich".to_string()),
+            ("org.opencontainers.image.licenses".to_string(), "".to_string()),
+            ("maintainer".to_string(), "someone".to_string()),
+        ]);
+        let metadata = metadata_from_labels(labels, Some("2024-06-01T00:00:00Z".to_string()));
+        assert_eq!(metadata.version.as_deref(), Some("v1.106.4"));
+        assert_eq!(metadata.source.as_deref(), Some("https://github.com/immich-app/immich"));
+        assert_eq!(metadata.revision, None);
+        assert_eq!(metadata.created.as_deref(), Some("2024-06-01T00:00:00Z"));
+        assert_eq!(metadata.licenses, None);
+    }
+
+    #[test]
+    fn test_cached_config() {
+        let config: ImageConfig = serde_json::from_str(
+            r#"{"created":"2024-06-01T00:00:00Z","architecture":"amd64","config":{"Labels":{"org.opencontainers.image.version":"16.1"}},"history":[]}"#,
+        )
+        .unwrap();
+        let stored: ImageConfig = serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        let labels = stored.config.and_then(|config| config.labels).unwrap_or_default();
+        assert_eq!(metadata_from_labels(labels, stored.created).version.as_deref(), Some("16.1"));
 
-    Ok(all_tags)
+        let now = chrono::DateTime::parse_from_rfc3339("2024-06-10T00:00:00Z").unwrap().with_timezone(&chrono::Utc);
+        assert!(config_is_fresh("2024-06-05T00:00:00+00:00", now));
+        assert!(!config_is_fresh("2024-06-01T00:00:00+00:00", now));
+        assert!(!config_is_fresh("not a date", now));
+    }
+
+    #[test]
+    fn test_strip_tag() {
+        assert_eq!(strip_tag("postgres:16.1"), "postgres");
+        assert_eq!(strip_tag("harbor.local:5000/dockerhub/library/postgres"), "harbor.local:5000/dockerhub/library/postgres");
+        assert_eq!(strip_tag("harbor.local:5000/library/postgres:16.1@sha256:abc"), "harbor.local:5000/library/postgres");
+    }
 }
@@ -0,0 +1,166 @@
+use crate::config::{SecretRef, SecretSource, Settings};
+use crate::kubernetes::client::Client;
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+// Secret settings are read from the config file, an environment variable, a file or a key
+// of a Kubernetes Secret. Files are read on every use so a rotated secret is picked up.
+// Kubernetes Secrets are fetched by `refresh` when the configuration is loaded and
+// periodically after, as most callers cannot wait on the Kubernetes API.
+
+static SECRET_REFS: LazyLock<RwLock<HashMap<SecretRef, String>>> = LazyLock::new(Default::default);
+
+/// The value of a secret setting, None when it is not set or cannot be read.
+pub fn resolve(source: &SecretSource) -> Option<String> {
+    let value = if let Some(value) = &source.value {
+        Some(value.0.clone())
+    } else if let Some(name) = &source.env_name {
+        std::env::var(name).ok()
+    } else if let Some(path) = &source.file {
+        std::fs::read_to_string(path)
+            .inspect_err(|e| log::error!("Failed to read secret file {}: {}", path, e))
+            .ok()
+            .map(|value| value.trim_end().to_string())
+    } else if let Some(secret_ref) = &source.secret_ref {
+        let value = SECRET_REFS.read().unwrap().get(secret_ref).cloned();
+        if value.is_none() {
+            log::error!("Key {} of Secret {}/{} has not been read", secret_ref.key, secret_ref.namespace, secret_ref.name);
+        }
+        value
+    } else {
+        None
+    };
+    value.filter(|value| !value.is_empty())
+}
+
+/// Fetch every Kubernetes Secret referenced by the settings, returning the ones that could
+/// not be read. A value read before is kept when fetching it again fails.
+pub async fn refresh(settings: &Settings) -> Vec<String> {
+    let mut refs: Vec<SecretRef> = settings
+        .secret_sources()
+        .into_iter()
+        .filter_map(|source| source.secret_ref)
+        .collect();
+    refs.sort();
+    refs.dedup();
+    if refs.is_empty() {
+        SECRET_REFS.write().unwrap().clear();
+        return Vec::new();
+    }
+
+    let client = match Client::new().await {
+        Ok(client) => client,
+        Err(e) => return vec![format!("Failed to connect to Kubernetes to read secrets: {}", e)],
+    };
+    let mut fetched = Vec::new();
+    for secret_ref in refs {
+        let value = client
+            .read_secret(&secret_ref.namespace, &secret_ref.name, &secret_ref.key)
+            .await
+            .map_err(|e| e.to_string());
+        fetched.push((secret_ref, value));
+    }
+    let (values, errors) = merge(&SECRET_REFS.read().unwrap(), fetched);
+    *SECRET_REFS.write().unwrap() = values;
+    errors
+}
+
+// The values to keep after fetching every referenced key, falling back to the previous
+// value of a key that could not be fetched, and the errors to report.
+fn merge(
+    previous: &HashMap<SecretRef, String>,
+    fetched: Vec<(SecretRef, Result<Option<String>, String>)>,
+) -> (HashMap<SecretRef, String>, Vec<String>) {
+    let mut errors = Vec::new();
+    let mut values = HashMap::new();
+    for (secret_ref, value) in fetched {
+        let describe = format!("key {} of Secret {}/{}", secret_ref.key, secret_ref.namespace, secret_ref.name);
+        match value {
+            Ok(Some(value)) => {
+                values.insert(secret_ref, value);
+                continue;
+            }
+            Ok(None) => errors.push(format!("{} does not exist", describe)),
+            Err(e) => errors.push(format!("Failed to read {}: {}", describe, e)),
+        }
+        if let Some(previous) = previous.get(&secret_ref) {
+            values.insert(secret_ref, previous.clone());
+        }
+    }
+    (values, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn secret_ref(name: &str) -> SecretRef {
+        SecretRef {
+            namespace: "slackwatch".to_string(),
+            name: name.to_string(),
+            key: "token".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "filetoken").unwrap();
+        let source = |path: &str| SecretSource {
+            file: Some(path.to_string()),
+            ..SecretSource::default()
+        };
+        assert_eq!(resolve(&source(file.path().to_str().unwrap())).as_deref(), Some("filetoken"));
+
+        // Read again on every use, so a rotated secret is picked up
+        file.as_file().set_len(0).unwrap();
+        assert_eq!(resolve(&source(file.path().to_str().unwrap())), None);
+        assert_eq!(resolve(&source("/nonexistent/slackwatch/token")), None);
+    }
+
+    #[test]
+    fn test_resolve_secret_ref() {
+        let cached = secret_ref("resolve-test");
+        SECRET_REFS.write().unwrap().insert(cached.clone(), "reftoken".to_string());
+        let source = |secret_ref: SecretRef| SecretSource {
+            secret_ref: Some(secret_ref),
+            ..SecretSource::default()
+        };
+        assert_eq!(resolve(&source(cached)).as_deref(), Some("reftoken"));
+        assert_eq!(resolve(&source(secret_ref("not-fetched"))), None);
+    }
+
+    #[test]
+    fn test_merge_keeps_previous_values() {
+        let previous = HashMap::from([
+            (secret_ref("rotated"), "old".to_string()),
+            (secret_ref("unreachable"), "kept".to_string()),
+            (secret_ref("deleted"), "kept too".to_string()),
+            (secret_ref("unreferenced"), "dropped".to_string()),
+        ]);
+        let fetched = vec![
+            (secret_ref("rotated"), Ok(Some("new".to_string()))),
+            (secret_ref("unreachable"), Err("connection refused".to_string())),
+            (secret_ref("deleted"), Ok(None)),
+            (secret_ref("missing"), Ok(None)),
+        ];
+        let (values, errors) = merge(&previous, fetched);
+        assert_eq!(
+            values,
+            HashMap::from([
+                (secret_ref("rotated"), "new".to_string()),
+                (secret_ref("unreachable"), "kept".to_string()),
+                (secret_ref("deleted"), "kept too".to_string()),
+            ])
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "Failed to read key token of Secret slackwatch/unreachable: connection refused",
+                "key token of Secret slackwatch/deleted does not exist",
+                "key token of Secret slackwatch/missing does not exist",
+            ]
+        );
+    }
+}
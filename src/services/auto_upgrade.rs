@@ -0,0 +1,169 @@
+use crate::config::current_settings;
+use crate::database::client::{has_auto_upgrade, insert_auto_upgrade};
+use crate::gitops::gitops::run_git_operations;
+use crate::models::models::{JobType, UpdateLevel, UpdateStatus, Workload};
+use crate::services::jobs::start_job;
+use crate::services::rollout::verify_rollout;
+use crate::services::scheduler::restart_scheduler;
+use crate::services::windows::in_maintenance_window;
+use crate::services::workloads::{running_version, update_level};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+// Workloads opt in with the `slackwatch.auto_upgrade` annotation, naming the largest update
+// applied without approval. The scheduler runs the gitops flow for them after a scan.
+
+// Schedules of the workloads found outside the maintenance windows
+static DEFERRED_SCHEDULES: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Whether upgrades wait for a maintenance window to open.
+pub fn has_deferred() -> bool {
+    !DEFERRED_SCHEDULES.lock().unwrap().is_empty()
+}
+
+/// Take the schedules to scan again once a maintenance window opens.
+pub fn take_deferred() -> Vec<String> {
+    std::mem::take(&mut *DEFERRED_SCHEDULES.lock().unwrap()).into_iter().collect()
+}
+
+// Ordered so that a policy allows every level below it
+fn rank(level: UpdateLevel) -> u8 {
+    match level {
+        UpdateLevel::Patch => 0,
+        UpdateLevel::Minor => 1,
+        UpdateLevel::Major => 2,
+    }
+}
+
+/// Check the update found for a workload against its policy, returning the update level
+/// or why it is not upgraded automatically.
+fn allowed_by_policy(workload: &Workload) -> Result<UpdateLevel, String> {
+    let policy = workload.auto_upgrade.as_deref().ok_or("no auto_upgrade policy")?;
+    let policy = UpdateLevel::from_str(policy).map_err(|_| format!("invalid auto_upgrade policy {:?}", policy))?;
+    if workload.update_available != UpdateStatus::Available {
+        return Err("no update available".to_string());
+    }
+    if workload.git_ops_repo.is_none() {
+        return Err("no slackwatch.repo to commit to".to_string());
+    }
+    let current = running_version(workload);
+    let level = update_level(current, &workload.latest_version)
+        .ok_or_else(|| format!("cannot compare {} to {}", current, workload.latest_version))?;
+    if rank(level) > rank(policy) {
+        return Err(format!("{} update is not allowed by the {} policy", level, policy));
+    }
+    Ok(level)
+}
+
+/// Upgrade the workloads of a scan whose policy allows their update, inside a maintenance
+/// window and at most `auto_upgrade.max_per_run` at a time. Outside the windows the
+/// workloads' schedules are scanned again when the next one opens. A version is upgraded
+/// automatically once, a failed upgrade is left to be retried by hand.
+pub async fn run_auto_upgrades(updates: &[Workload]) {
+    let candidates: Vec<&Workload> = updates
+        .iter()
+        .filter(|workload| workload.auto_upgrade.is_some())
+        .collect();
+    if candidates.is_empty() {
+        return;
+    }
+    let settings = current_settings();
+    match in_maintenance_window(&settings, chrono::Utc::now()) {
+        Ok(true) => {}
+        Ok(false) => {
+            log::info!("Outside the maintenance windows, deferring {} auto upgrades", candidates.len());
+            let mut deferred = DEFERRED_SCHEDULES.lock().unwrap();
+            deferred.extend(
+                candidates
+                    .iter()
+                    .filter(|workload| allowed_by_policy(workload).is_ok())
+                    .map(|workload| settings.schedule_of(workload).to_string()),
+            );
+            restart_scheduler();
+            return;
+        }
+        Err(e) => {
+            log::error!("Invalid maintenance window, skipping auto upgrades: {}", e);
+            return;
+        }
+    }
+    let max_per_run = settings.auto_upgrade.max_per_run;
+
+    let mut started = 0;
+    for workload in candidates {
+        let target = format!("{}/{}", workload.namespace, workload.name);
+        let level = match allowed_by_policy(workload) {
+            Ok(level) => level,
+            Err(reason) => {
+                log::info!("Not upgrading {} automatically: {}", target, reason);
+                continue;
+            }
+        };
+        match has_auto_upgrade(&workload.namespace, &workload.name, &workload.latest_version) {
+            Ok(false) => {}
+            Ok(true) => {
+                log::info!("{} was already upgraded to {} automatically", target, workload.latest_version);
+                continue;
+            }
+            Err(e) => {
+                log::error!("Failed to look up auto upgrades of {}: {}", target, e);
+                continue;
+            }
+        }
+        if started >= max_per_run {
+            log::info!("Started {} auto upgrades, deferring the rest to the next run", started);
+            break;
+        }
+        let job = match start_job(JobType::Upgrade, Some(&target)) {
+            Ok(job) => job,
+            Err(e) => {
+                log::error!("Error starting auto upgrade of {}: {}", target, e);
+                continue;
+            }
+        };
+        insert_auto_upgrade(&workload.namespace, &workload.name, &workload.latest_version, job.id)
+            .unwrap_or_else(|e| log::error!("Failed to record auto upgrade of {}: {}", target, e));
+        started += 1;
+        log::info!(
+            "Upgrading {} from {} to {} automatically ({} update)",
+            target, workload.current_version, workload.latest_version, level
+        );
+        // Commits run one at a time, rollouts are watched alongside the next upgrades
+        if let Err(e) = run_git_operations(workload.clone()).await.map_err(|e| e.to_string()) {
+            log::error!("Failed to upgrade workload {} automatically: {}", target, e);
+            job.finish(Err(e));
+            continue;
+        }
+        let workload = workload.clone();
+        tokio::task::spawn(async move {
+            let result = verify_rollout(&workload, &job).await.map(|mut result| {
+                result["automatic"] = json!(true);
+                result
+            });
+            job.finish(result);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_by_policy() {
+        let workload = |current: &str, latest: &str, policy: &str| Workload {
+            git_ops_repo: Some("homelab".to_string()),
+            update_available: UpdateStatus::Available,
+            auto_upgrade: Some(policy.to_string()),
+            ..Workload::for_test("postgres", &format!("postgres:{}", current), current, latest)
+        };
+        assert_eq!(allowed_by_policy(&workload("16.1.0", "16.1.2", "patch")), Ok(UpdateLevel::Patch));
+        assert_eq!(allowed_by_policy(&workload("16.1.0", "16.1.2", "minor")), Ok(UpdateLevel::Patch));
+        assert_eq!(allowed_by_policy(&workload("16.1.0", "16.2.0", "minor")), Ok(UpdateLevel::Minor));
+        assert!(allowed_by_policy(&workload("16.1.0", "16.2.0", "patch")).is_err());
+        assert!(allowed_by_policy(&workload("16.1.0", "17.0.0", "minor")).is_err());
+        assert!(allowed_by_policy(&workload("16.1.0", "16.1.2", "always")).is_err());
+    }
+}
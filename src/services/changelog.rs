@@ -0,0 +1,294 @@
+use crate::config::current_settings;
+use crate::database::client::{
+    insert_release_note_miss, insert_release_notes, return_release_note_miss, return_release_notes,
+};
+use crate::models::models::{ReleaseNote, UpdateStatus, Workload};
+use crate::secrets::resolve;
+use crate::services::workloads::{running_version, strip_tag_lettings};
+use chrono::{DateTime, Utc};
+use reqwest::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
+use semver::Version;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+// Release notes are looked up on the forge hosting an image's source, from the
+// `slackwatch.source` annotation or the image's OCI source label.
+
+// How long a source whose releases lacked the latest version is left alone
+const MISS_RETRY_HOURS: i64 = 24;
+
+#[derive(Debug, PartialEq)]
+enum SourceRepo {
+    GitHub { owner: String, repo: String },
+    GitLab { host: String, path: String },
+}
+
+/// Recognise GitHub and GitLab repositories in https, scheme-less and ssh style URLs.
+fn parse_source(source: &str) -> Option<SourceRepo> {
+    let source = source.trim();
+    let source = source.split_once("://").map_or(source, |(_, rest)| rest);
+    let source = match source.strip_prefix("git@") {
+        Some(rest) => rest.replacen(':', "/", 1),
+        None => source.to_string(),
+    };
+    let source = source.trim_end_matches('/');
+    let source = source.strip_suffix(".git").unwrap_or(source);
+    let (host, path) = source.split_once('/')?;
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if host == "github.com" {
+        match segments.as_slice() {
+            [owner, repo, ..] => Some(SourceRepo::GitHub {
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+            }),
+            _ => None,
+        }
+    } else if host.contains("gitlab") && segments.len() >= 2 {
+        // GitLab links to pages below a project after a `-` segment
+        let end = segments.iter().position(|s| *s == "-").unwrap_or(segments.len());
+        Some(SourceRepo::GitLab {
+            host: host.to_string(),
+            path: segments[..end].join("/"),
+        })
+    } else {
+        None
+    }
+}
+
+impl SourceRepo {
+    fn releases_page(&self) -> String {
+        match self {
+            SourceRepo::GitHub { owner, repo } => format!("https://github.com/{}/{}/releases", owner, repo),
+            SourceRepo::GitLab { host, path } => format!("https://{}/{}/-/releases", host, path),
+        }
+    }
+
+    async fn fetch_releases(&self, source: &str) -> Result<Vec<ReleaseNote>, reqwest::Error> {
+        match self {
+            SourceRepo::GitHub { owner, repo } => {
+                let url = format!("https://api.github.com/repos/{}/{}/releases?per_page=100", owner, repo);
+                let token = resolve(&current_settings().release_notes.github_token());
+                let releases: Vec<GitHubRelease> = get_json(&url, token.as_deref()).await?;
+                Ok(releases
+                    .into_iter()
+                    .map(|release| ReleaseNote {
+                        source: source.to_string(),
+                        version: release.tag_name,
+                        title: release.name,
+                        url: release.html_url,
+                        body: release.body,
+                        published_at: release.published_at,
+                    })
+                    .collect())
+            }
+            SourceRepo::GitLab { host, path } => {
+                let url = format!(
+                    "https://{}/api/v4/projects/{}/releases?per_page=100",
+                    host,
+                    path.replace('/', "%2F")
+                );
+                let releases: Vec<GitLabRelease> = get_json(&url, None).await?;
+                Ok(releases
+                    .into_iter()
+                    .map(|release| ReleaseNote {
+                        source: source.to_string(),
+                        url: format!("https://{}/{}/-/releases/{}", host, path, release.tag_name),
+                        version: release.tag_name,
+                        title: release.name,
+                        body: release.description,
+                        published_at: release.released_at,
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GitHubRelease {
+    tag_name: String,
+    name: Option<String>,
+    html_url: String,
+    body: Option<String>,
+    published_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitLabRelease {
+    tag_name: String,
+    name: Option<String>,
+    description: Option<String>,
+    released_at: Option<String>,
+}
+
+async fn get_json<T: DeserializeOwned>(url: &str, token: Option<&str>) -> Result<T, reqwest::Error> {
+    let mut request = reqwest::Client::new()
+        .get(url)
+        .header(USER_AGENT, "slackwatch")
+        .header(ACCEPT, "application/json");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+    request
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
+fn parse_version(tag: &str) -> Option<Version> {
+    Version::parse(&strip_tag_lettings(tag)).ok()
+}
+
+/// Whether the releases fetched at `checked_at` lacked the version looked for recently enough
+/// not to fetch them again.
+fn recently_missed(checked_at: Option<&str>, now: DateTime<Utc>) -> bool {
+    checked_at
+        .and_then(|checked_at| DateTime::parse_from_rfc3339(checked_at).ok())
+        .is_some_and(|checked_at| now - checked_at.with_timezone(&Utc) < chrono::Duration::hours(MISS_RETRY_HOURS))
+}
+
+/// Releases after `current` up to and including `latest`, newest first.
+fn notes_between(notes: Vec<ReleaseNote>, current: &str, latest: &str) -> Vec<ReleaseNote> {
+    let (Some(current), Some(latest)) = (parse_version(current), parse_version(latest)) else {
+        return Vec::new();
+    };
+    let mut notes: Vec<(Version, ReleaseNote)> = notes
+        .into_iter()
+        .filter_map(|note| parse_version(&note.version).map(|version| (version, note)))
+        .filter(|(version, _)| *version > current && *version <= latest)
+        .collect();
+    notes.sort_by(|a, b| b.0.cmp(&a.0));
+    notes.into_iter().map(|(_, note)| note).collect()
+}
+
+/// Stored release notes between the current and latest version of a workload.
+pub fn stored_release_notes(workload: &Workload) -> Result<Vec<ReleaseNote>, String> {
+    let Some(source) = &workload.source else {
+        return Ok(Vec::new());
+    };
+    let notes = return_release_notes(source).map_err(|e| e.to_string())?;
+    Ok(notes_between(notes, running_version(workload), &workload.latest_version))
+}
+
+/// Find the source of a workload with an update available, store its release notes and
+/// link the notes of the latest version, or the source's release list when it has none.
+pub async fn attach_changelog(mut workload: Workload) -> Workload {
+    if workload.update_available != UpdateStatus::Available {
+        return workload;
+    }
+    if workload.source.is_none() {
+        workload.source = [&workload.current_metadata, &workload.latest_metadata]
+            .into_iter()
+            .flatten()
+            .find_map(|metadata| metadata.source.clone());
+    }
+    let Some(source) = workload.source.clone() else {
+        return workload;
+    };
+    let Some(repo) = parse_source(&source) else {
+        log::info!("No release notes lookup for source {} of {}", source, workload.name);
+        return workload;
+    };
+
+    let latest = parse_version(&workload.latest_version);
+    let has_latest = |notes: &[ReleaseNote]| {
+        notes.iter().any(|note| latest.is_some() && parse_version(&note.version) == latest)
+    };
+    let stored = return_release_notes(&source).unwrap_or_else(|e| {
+        log::error!("Failed to load release notes for {}: {}", source, e);
+        Vec::new()
+    });
+    let missed = return_release_note_miss(&source, &workload.latest_version).unwrap_or_else(|e| {
+        log::error!("Failed to load release note lookups for {}: {}", source, e);
+        None
+    });
+    let notes = if has_latest(&stored) {
+        stored
+    } else if recently_missed(missed.as_deref(), Utc::now()) {
+        log::debug!("Releases of {} lacked {} when last fetched", source, workload.latest_version);
+        stored
+    } else {
+        match repo.fetch_releases(&source).await {
+            Ok(fetched) => {
+                insert_release_notes(&fetched)
+                    .unwrap_or_else(|e| log::error!("Failed to store release notes for {}: {}", source, e));
+                if !has_latest(&fetched) {
+                    insert_release_note_miss(&source, &workload.latest_version)
+                        .unwrap_or_else(|e| log::error!("Failed to record release note lookup for {}: {}", source, e));
+                }
+                fetched
+            }
+            Err(e) => {
+                log::warn!("Failed to fetch release notes for {}: {}", source, e);
+                stored
+            }
+        }
+    };
+
+    let notes = notes_between(notes, running_version(&workload), &workload.latest_version);
+    log::info!(
+        "{} releases of {} between {} and {}",
+        notes.len(), source, workload.current_version, workload.latest_version
+    );
+    workload.changelog_url = Some(
+        notes
+            .first()
+            .map(|note| note.url.clone())
+            .unwrap_or_else(|| repo.releases_page()),
+    );
+    workload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source() {
+        let github = SourceRepo::GitHub {
+            owner: "immich-app".to_string(),
+            repo: "immich".to_string(),
+        };
+        assert_eq!(parse_source("https://github.com/immich-app/immich"), Some(github));
+        assert_eq!(
+            parse_source("git@github.com:immich-app/immich.git"),
+            parse_source("github.com/immich-app/immich/")
+        );
+        assert_eq!(
+            parse_source("https://gitlab.com/group/sub/project/-/tree/main"),
+            Some(SourceRepo::GitLab {
+                host: "gitlab.com".to_string(),
+                path: "group/sub/project".to_string(),
+            })
+        );
+        assert_eq!(parse_source("https://example.com/owner/repo"), None);
+    }
+
+    #[test]
+    fn test_notes_between() {
+        let note = |version: &str| ReleaseNote {
+            source: "https://github.com/postgres/postgres".to_string(),
+            version: version.to_string(),
+            title: None,
+            url: format!("https://github.com/postgres/postgres/releases/tag/{}", version),
+            body: None,
+            published_at: None,
+        };
+        let notes = vec![note("v16.1.0"), note("v16.2.0"), note("v16.3.0"), note("v17.0.0"), note("nightly")];
+        let versions: Vec<String> = notes_between(notes, "16.1.0", "16.3.0")
+            .into_iter()
+            .map(|note| note.version)
+            .collect();
+        assert_eq!(versions, vec!["v16.3.0", "v16.2.0"]);
+    }
+
+    #[test]
+    fn test_recently_missed() {
+        let now = DateTime::parse_from_rfc3339("2024-01-06T12:00:00Z").unwrap().with_timezone(&Utc);
+        assert!(recently_missed(Some("2024-01-06T01:00:00+00:00"), now));
+        assert!(!recently_missed(Some("2024-01-05T11:00:00+00:00"), now));
+        assert!(!recently_missed(None, now));
+    }
+}
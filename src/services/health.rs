@@ -0,0 +1,92 @@
+use crate::config::current_settings;
+use crate::database::client::check_writable;
+use crate::kubernetes::client::Client;
+use crate::services::reload::last_reload_error;
+use crate::services::scheduler::{last_heartbeat, scheduler_alive};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// A problem that does not fail the check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+impl Check {
+    fn from_result<E: ToString>(result: Result<(), E>) -> Self {
+        match result {
+            Ok(_) => Check {
+                ok: true,
+                error: None,
+                warning: None,
+            },
+            Err(e) => Check {
+                ok: false,
+                error: Some(e.to_string()),
+                warning: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: String,
+    pub checks: BTreeMap<&'static str, Check>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_heartbeat: Option<String>,
+}
+
+impl HealthReport {
+    fn new(checks: BTreeMap<&'static str, Check>) -> Self {
+        let status = if checks.values().all(|check| check.ok) { "ok" } else { "failing" };
+        HealthReport {
+            status: status.to_string(),
+            checks,
+            last_heartbeat: last_heartbeat().map(|ts| ts.to_rfc3339()),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// Liveness: the process is serving requests and the scheduler task is still running.
+pub fn liveness() -> HealthReport {
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "scheduler",
+        Check::from_result(if scheduler_alive() {
+            Ok(())
+        } else {
+            Err("Scheduler task is not running")
+        }),
+    );
+    HealthReport::new(checks)
+}
+
+/// Readiness: the database is writable, the Kubernetes API answers and the config in effect
+/// is valid. A rejected reload is reported without failing, the previous config keeps running.
+pub async fn readiness() -> HealthReport {
+    let mut checks = BTreeMap::new();
+    checks.insert("database", Check::from_result(check_writable()));
+    let kubernetes = match Client::new().await {
+        Ok(client) => client.apiserver_version().await,
+        Err(e) => Err(e),
+    };
+    checks.insert("kubernetes", Check::from_result(kubernetes));
+    let config = current_settings().validate().map_err(|errors| errors.join("; "));
+    checks.insert(
+        "config",
+        Check {
+            warning: last_reload_error(),
+            ..Check::from_result(config)
+        },
+    );
+    HealthReport::new(checks)
+}
@@ -0,0 +1,146 @@
+use crate::database::client::{insert_ignore, return_ignores};
+use crate::models::models::{Ignore, ImageTag, Workload};
+use crate::services::workloads::strip_tag_lettings;
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use semver::{Version, VersionReq};
+
+#[derive(Debug)]
+pub enum IgnoreError {
+    Invalid(String),
+    Database(String),
+}
+
+impl std::fmt::Display for IgnoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IgnoreError::Invalid(e) => write!(f, "Invalid ignore: {}", e),
+            IgnoreError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+/// Parse `until` as RFC 3339, or as a plain `YYYY-MM-DD` date meaning the start of that day in UTC.
+pub fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(until) = DateTime::parse_from_rfc3339(value) {
+        return Ok(until.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| format!("until {:?} is not an RFC 3339 time or YYYY-MM-DD date", value))
+}
+
+/// Validate and store an ignore for a workload.
+pub fn create_ignore(
+    namespace: &str,
+    name: &str,
+    version: Option<&str>,
+    version_range: Option<&str>,
+    until: Option<&str>,
+) -> Result<Ignore, IgnoreError> {
+    if version.is_none() && version_range.is_none() && until.is_none() {
+        return Err(IgnoreError::Invalid(
+            "one of version, version_range or until is required".to_string(),
+        ));
+    }
+    if let Some(range) = version_range {
+        VersionReq::parse(range)
+            .map_err(|e| IgnoreError::Invalid(format!("version_range {:?}: {}", range, e)))?;
+    }
+    let until = until
+        .map(parse_until)
+        .transpose()
+        .map_err(IgnoreError::Invalid)?
+        .map(|until| until.to_rfc3339_opts(SecondsFormat::Secs, true));
+    insert_ignore(namespace, name, version, version_range, until.as_deref())
+        .map_err(|e| IgnoreError::Database(e.to_string()))
+}
+
+impl Ignore {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.until
+            .as_deref()
+            .is_none_or(|until| parse_until(until).is_ok_and(|until| until > now))
+    }
+
+    /// Whether this ignore applies to `tag`. Snoozes without a version or range match every tag.
+    pub fn matches(&self, tag: &str) -> bool {
+        if self.version.is_none() && self.version_range.is_none() {
+            return true;
+        }
+        if self.version.as_deref() == Some(tag) {
+            return true;
+        }
+        let range = self.version_range.as_deref().and_then(|range| VersionReq::parse(range).ok());
+        match (range, Version::parse(&strip_tag_lettings(tag))) {
+            (Some(range), Ok(version)) => range.matches(&version),
+            _ => false,
+        }
+    }
+}
+
+/// Drop tags covered by the workload's active ignores.
+pub fn apply_ignores(workload: &Workload, tags: Vec<ImageTag>) -> Vec<ImageTag> {
+    let ignores = return_ignores(&workload.namespace, &workload.name).unwrap_or_else(|e| {
+        log::error!("Failed to load ignores for {}: {}", workload.name, e);
+        Vec::new()
+    });
+    filter_ignored(&ignores, tags, Utc::now())
+}
+
+fn filter_ignored(ignores: &[Ignore], mut tags: Vec<ImageTag>, now: DateTime<Utc>) -> Vec<ImageTag> {
+    let active: Vec<&Ignore> = ignores.iter().filter(|ignore| ignore.is_active(now)).collect();
+    tags.retain(|tag| {
+        let ignored = active.iter().any(|ignore| ignore.matches(&tag.name));
+        if ignored {
+            log::debug!("Ignoring tag {}", tag.name);
+        }
+        !ignored
+    });
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(version: Option<&str>, version_range: Option<&str>, until: Option<&str>) -> Ignore {
+        Ignore {
+            id: 1,
+            namespace: "default".to_string(),
+            name: "postgres".to_string(),
+            version: version.map(String::from),
+            version_range: version_range.map(String::from),
+            until: until.map(String::from),
+            created_at: String::new(),
+        }
+    }
+
+    fn tags(names: &[&str]) -> Vec<ImageTag> {
+        names
+            .iter()
+            .map(|name| ImageTag { name: name.to_string(), published: None })
+            .collect()
+    }
+
+    fn names(tags: Vec<ImageTag>) -> Vec<String> {
+        tags.into_iter().map(|tag| tag.name).collect()
+    }
+
+    #[test]
+    fn test_filter_ignored() {
+        let now = parse_until("2025-06-01").unwrap();
+        let all = || tags(&["16.2.0", "16.3.0", "17.0.0", "17.1.0"]);
+
+        let version = [ignore(Some("16.3.0"), None, None)];
+        assert_eq!(names(filter_ignored(&version, all(), now)), ["16.2.0", "17.0.0", "17.1.0"]);
+
+        let range = [ignore(None, Some(">=17, <18"), None)];
+        assert_eq!(names(filter_ignored(&range, all(), now)), ["16.2.0", "16.3.0"]);
+
+        let snoozed = [ignore(None, None, Some("2025-07-01T00:00:00Z"))];
+        assert!(filter_ignored(&snoozed, all(), now).is_empty());
+
+        let expired = [ignore(Some("16.3.0"), None, Some("2025-05-01"))];
+        assert_eq!(filter_ignored(&expired, all(), now).len(), 4);
+    }
+}
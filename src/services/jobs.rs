@@ -0,0 +1,350 @@
+use crate::database::client::{finish_job, insert_job};
+use crate::models::models::{JobState, JobType};
+use crate::services::scheduler::scan_finished;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::broadcast;
+
+// Number of finished jobs whose events are kept around for late subscribers
+const RETAINED_JOBS: usize = 20;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressKind {
+    JobStarted,
+    Started {
+        namespace: String,
+        name: String,
+        image: String,
+    },
+    TagsFetched {
+        namespace: String,
+        name: String,
+        count: usize,
+    },
+    UpdateFound {
+        namespace: String,
+        name: String,
+        current_version: String,
+        latest_version: String,
+    },
+    Error {
+        namespace: String,
+        name: String,
+        error: String,
+    },
+    Finished {
+        namespace: String,
+        name: String,
+    },
+    Rollout {
+        namespace: String,
+        name: String,
+        status: String,
+    },
+    JobFinished {
+        success: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+impl std::fmt::Display for ProgressKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgressKind::JobStarted => write!(f, "Job started"),
+            ProgressKind::Started { namespace, name, image } => {
+                write!(f, "{}/{}: checking {}", namespace, name, image)
+            }
+            ProgressKind::TagsFetched { namespace, name, count } => {
+                write!(f, "{}/{}: fetched {} tags", namespace, name, count)
+            }
+            ProgressKind::UpdateFound { namespace, name, current_version, latest_version } => write!(
+                f,
+                "{}/{}: update available {} -> {}",
+                namespace, name, current_version, latest_version
+            ),
+            ProgressKind::Error { namespace, name, error } => {
+                write!(f, "{}/{}: error {}", namespace, name, error)
+            }
+            ProgressKind::Finished { namespace, name } => write!(f, "{}/{}: done", namespace, name),
+            ProgressKind::Rollout { namespace, name, status } => {
+                write!(f, "{}/{}: rollout {}", namespace, name, status)
+            }
+            ProgressKind::JobFinished { success: true, .. } => write!(f, "Job succeeded"),
+            ProgressKind::JobFinished { error, .. } => {
+                write!(f, "Job failed: {}", error.as_deref().unwrap_or("unknown error"))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    /// A full scan is already running, holds its job id
+    AlreadyRunning(i64),
+    Database(String),
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::AlreadyRunning(id) => write!(f, "A full scan is already running as job {}", id),
+            JobError::Database(e) => write!(f, "Failed to record job: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    pub job_id: i64,
+    pub seq: usize,
+    pub timestamp: String,
+    #[serde(flatten)]
+    pub kind: ProgressKind,
+}
+
+#[derive(Default)]
+struct JobEvents {
+    events: Vec<ProgressEvent>,
+    finished: bool,
+}
+
+static RUNNING_SCAN: Mutex<Option<i64>> = Mutex::new(None);
+static JOBS: LazyLock<Mutex<BTreeMap<i64, JobEvents>>> = LazyLock::new(Default::default);
+static FINISHED: LazyLock<Mutex<VecDeque<i64>>> = LazyLock::new(Default::default);
+static EVENTS: LazyLock<broadcast::Sender<ProgressEvent>> =
+    LazyLock::new(|| broadcast::channel(1024).0);
+
+// Reports progress of a running job to anyone subscribed to its event stream.
+// Events are kept in memory while the job runs and persisted with the job once it finishes.
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    pub id: i64,
+    pub job_type: JobType,
+    guard: Arc<JobGuard>,
+}
+
+// Shared by the clones of a handle. Fails the job when the last one is dropped before it
+// finished, e.g. because its task panicked, so a full scan never holds the lock for good.
+#[derive(Debug)]
+struct JobGuard {
+    id: i64,
+    job_type: JobType,
+    finished: AtomicBool,
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        if self.finished.load(Ordering::SeqCst) {
+            return;
+        }
+        log::error!("Job {} stopped before it finished", self.id);
+        JobHandle::new(self.id, self.job_type).finish(Err("Job stopped before it finished".to_string()));
+    }
+}
+
+impl JobHandle {
+    fn new(id: i64, job_type: JobType) -> Self {
+        JobHandle {
+            id,
+            job_type,
+            guard: Arc::new(JobGuard {
+                id,
+                job_type,
+                finished: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn emit(&self, kind: ProgressKind) {
+        let mut jobs = JOBS.lock().unwrap();
+        let job = jobs.entry(self.id).or_default();
+        let event = ProgressEvent {
+            job_id: self.id,
+            seq: job.events.len(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            kind,
+        };
+        job.events.push(event.clone());
+        // Sent while holding the lock so subscribers never miss or duplicate an event
+        let _ = EVENTS.send(event);
+    }
+
+    /// Counts of workloads checked, updates found and errors reported by this job.
+    pub fn summary(&self) -> Value {
+        let jobs = JOBS.lock().unwrap();
+        let events = jobs.get(&self.id).map(|job| job.events.as_slice()).unwrap_or_default();
+        let count = |f: fn(&ProgressKind) -> bool| events.iter().filter(|e| f(&e.kind)).count();
+        json!({
+            "workloads": count(|k| matches!(k, ProgressKind::Started { .. })),
+            "updates": count(|k| matches!(k, ProgressKind::UpdateFound { .. })),
+            "errors": count(|k| matches!(k, ProgressKind::Error { .. })),
+        })
+    }
+
+    /// Record the result of the job. Only the first result of a job is kept.
+    pub fn finish(&self, result: Result<Value, String>) {
+        let Some(log) = self.close(&result) else {
+            return;
+        };
+        let (state, value, error) = match &result {
+            Ok(value) => (JobState::Succeeded, Some(value), None),
+            Err(e) => (JobState::Failed, None, Some(e.as_str())),
+        };
+        finish_job(self.id, state, value, error, &log)
+            .unwrap_or_else(|e| log::error!("Failed to record result of job {}: {}", self.id, e));
+    }
+
+    // Mark the job finished in memory and release the scan lock, returning the job log to
+    // persist. None when the job already finished.
+    fn close(&self, result: &Result<Value, String>) -> Option<Vec<String>> {
+        if self.guard.finished.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        self.emit(ProgressKind::JobFinished {
+            success: result.is_ok(),
+            error: result.as_ref().err().cloned(),
+        });
+        let log = job_log(self.id).unwrap_or_default();
+
+        if self.job_type == JobType::ScanAll {
+            let released = {
+                let mut running = RUNNING_SCAN.lock().unwrap();
+                let released = *running == Some(self.id);
+                if released {
+                    *running = None;
+                }
+                released
+            };
+            if released {
+                scan_finished();
+            }
+        }
+        if let Some(job) = JOBS.lock().unwrap().get_mut(&self.id) {
+            job.finished = true;
+        }
+        let mut finished = FINISHED.lock().unwrap();
+        finished.push_back(self.id);
+        while finished.len() > RETAINED_JOBS {
+            if let Some(expired) = finished.pop_front() {
+                JOBS.lock().unwrap().remove(&expired);
+            }
+        }
+        Some(log)
+    }
+}
+
+/// Record a new running job. Only one full scan may run at a time.
+pub fn start_job(job_type: JobType, target: Option<&str>) -> Result<JobHandle, JobError> {
+    let mut running = RUNNING_SCAN.lock().unwrap();
+    if job_type == JobType::ScanAll {
+        if let Some(id) = *running {
+            return Err(JobError::AlreadyRunning(id));
+        }
+    }
+    let id = insert_job(job_type, target).map_err(|e| JobError::Database(e.to_string()))?;
+    if job_type == JobType::ScanAll {
+        *running = Some(id);
+    }
+    let job = JobHandle::new(id, job_type);
+    job.emit(ProgressKind::JobStarted);
+    Ok(job)
+}
+
+/// Whether a full scan is running.
+pub fn scan_running() -> bool {
+    RUNNING_SCAN.lock().unwrap().is_some()
+}
+
+/// Log lines of a job that is still held in memory.
+pub fn job_log(job_id: i64) -> Option<Vec<String>> {
+    let jobs = JOBS.lock().unwrap();
+    let job = jobs.get(&job_id)?;
+    Some(
+        job.events
+            .iter()
+            .map(|event| format!("{} {}", event.timestamp, event.kind))
+            .collect(),
+    )
+}
+
+/// Events emitted so far for a job plus a receiver for the ones still to come.
+/// The receiver is None once the job has finished.
+pub fn subscribe(
+    job_id: i64,
+) -> Option<(Vec<ProgressEvent>, Option<broadcast::Receiver<ProgressEvent>>)> {
+    let jobs = JOBS.lock().unwrap();
+    let job = jobs.get(&job_id)?;
+    let receiver = (!job.finished).then(|| EVENTS.subscribe());
+    Some((job.events.clone(), receiver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscriber_receives_history_and_live_events() {
+        let job = JobHandle::new(-1, JobType::ScanOne);
+        job.emit(ProgressKind::JobStarted);
+        job.emit(ProgressKind::Started {
+            namespace: "default".to_string(),
+            name: "postgres".to_string(),
+            image: "postgres:16.1".to_string(),
+        });
+
+        let (history, receiver) = subscribe(job.id).unwrap();
+        assert_eq!(history.len(), 2);
+        let mut receiver = receiver.unwrap();
+
+        job.emit(ProgressKind::UpdateFound {
+            namespace: "default".to_string(),
+            name: "postgres".to_string(),
+            current_version: "16.1".to_string(),
+            latest_version: "16.2".to_string(),
+        });
+        let event = loop {
+            let event = receiver.recv().await.unwrap();
+            if event.job_id == job.id {
+                break event;
+            }
+        };
+        assert_eq!(event.seq, 2);
+        assert_eq!(job.summary(), json!({ "workloads": 1, "updates": 1, "errors": 0 }));
+        assert_eq!(
+            job_log(job.id).unwrap()[2].split_once(' ').unwrap().1,
+            "default/postgres: update available 16.1 -> 16.2"
+        );
+
+        // Finishing is recorded in memory here, persisting the job needs the database
+        assert!(job.close(&Ok(json!({}))).is_some());
+        let event = loop {
+            let event = receiver.recv().await.unwrap();
+            if event.job_id == job.id {
+                break event;
+            }
+        };
+        assert_eq!(event.seq, 3);
+        assert_eq!(event.kind, ProgressKind::JobFinished { success: true, error: None });
+        assert!(job.close(&Err("again".to_string())).is_none());
+
+        let (history, receiver) = subscribe(job.id).unwrap();
+        assert_eq!(history.len(), 4);
+        assert!(receiver.is_none());
+    }
+
+    #[test]
+    fn test_finishing_releases_the_scan_lock() {
+        let job = JobHandle::new(-2, JobType::ScanAll);
+        *RUNNING_SCAN.lock().unwrap() = Some(job.id);
+        let clone = job.clone();
+        drop(job);
+        assert_eq!(*RUNNING_SCAN.lock().unwrap(), Some(-2));
+        clone.close(&Err("stopped".to_string()));
+        assert_eq!(*RUNNING_SCAN.lock().unwrap(), None);
+    }
+}
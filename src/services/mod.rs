@@ -1,4 +1,12 @@
+pub mod auto_upgrade;
+pub mod changelog;
+pub mod health;
+pub mod ignores;
+pub mod jobs;
+pub mod reload;
+pub mod rollout;
 pub mod scheduler;
+pub mod windows;
 pub mod workloads;
 
 //fetch watched workloads and update database
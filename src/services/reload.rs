@@ -0,0 +1,154 @@
+use crate::config::{current_settings, replace_settings, Settings};
+use crate::kubernetes::client::find_enabled_workloads;
+use crate::repocheck::repocheck;
+use crate::secrets;
+use crate::services::scheduler::restart_scheduler;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// The config file is polled rather than watched. Kubernetes updates a mounted ConfigMap by
+// swapping a symlink, which reading through the path picks up like any other edit.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// How often secrets held in Kubernetes Secrets are read again when the config is unchanged
+const SECRET_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+// Extensions the config crate tries for a file name given without one
+const EXTENSIONS: [&str; 7] = ["toml", "json", "yaml", "yml", "ini", "ron", "json5"];
+
+// Why the last reload was rejected, cleared by the next one applied
+static LAST_RELOAD_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Why the configuration last failed to reload, None once a reload succeeds.
+pub fn last_reload_error() -> Option<String> {
+    LAST_RELOAD_ERROR.lock().unwrap().clone()
+}
+
+/// Hash of every config file `Settings::new` may read, to notice when one changes.
+fn fingerprint() -> u64 {
+    let env_config = std::env::var("SLACKWATCH_CONFIG").unwrap_or_default();
+    let mut hasher = DefaultHasher::new();
+    for name in ["/app/config/config", ".env.yaml", env_config.as_str()] {
+        if name.is_empty() {
+            continue;
+        }
+        let candidates = std::iter::once(name.to_string())
+            .chain(EXTENSIONS.iter().map(|extension| format!("{}.{}", name, extension)));
+        for path in candidates {
+            if let Ok(contents) = std::fs::read(&path) {
+                path.hash(&mut hasher);
+                contents.hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+pub fn spawn_config_watcher() {
+    tokio::task::spawn(async {
+        let mut last = fingerprint();
+        let mut secrets_refreshed = Instant::now();
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let current = fingerprint();
+            if current != last {
+                last = current;
+                secrets_refreshed = Instant::now();
+                log::info!("Configuration changed, reloading");
+                if let Err(e) = reload().await {
+                    log::error!("Keeping the previous configuration: {}", e);
+                }
+            } else if secrets_refreshed.elapsed() >= SECRET_REFRESH_INTERVAL {
+                secrets_refreshed = Instant::now();
+                refresh_secrets(&current_settings()).await;
+            }
+        }
+    });
+}
+
+/// Load and validate the configuration, then apply it. Invalid configuration is not applied.
+pub async fn reload() -> Result<(), String> {
+    let settings = load().inspect_err(|e| *LAST_RELOAD_ERROR.lock().unwrap() = Some(e.clone()))?;
+    *LAST_RELOAD_ERROR.lock().unwrap() = None;
+    refresh_secrets(&settings).await;
+    apply(settings);
+    tokio::task::spawn(check_workloads());
+    Ok(())
+}
+
+fn load() -> Result<Settings, String> {
+    let settings = Settings::new().map_err(|e| format!("Failed to load settings: {}", e))?;
+    settings
+        .validate()
+        .map_err(|errors| format!("Invalid configuration: {}", errors.join("; ")))?;
+    Ok(settings)
+}
+
+/// Check the annotations of every watched workload against the settings in effect and
+/// log all problems found, rather than running into them one at a time while scanning.
+pub async fn check_workloads() {
+    let workloads = match find_enabled_workloads().await {
+        Ok(workloads) => workloads,
+        Err(e) => {
+            log::warn!("Failed to list workloads to check their annotations: {}", e);
+            return;
+        }
+    };
+    let settings = current_settings();
+    let errors: Vec<String> = workloads
+        .iter()
+        .flat_map(|workload| settings.validate_workload(workload))
+        .collect();
+    for error in &errors {
+        log::warn!("Invalid workload annotation: {}", error);
+    }
+    if !errors.is_empty() {
+        log::warn!("Found {} problems in the annotations of {} workloads", errors.len(), workloads.len());
+    }
+}
+
+/// Read the Kubernetes Secrets referenced by `settings`, logging the ones that cannot be read.
+pub async fn refresh_secrets(settings: &Settings) {
+    for error in secrets::refresh(settings).await {
+        log::error!("{}", error);
+    }
+}
+
+fn apply(settings: Settings) {
+    repocheck::configure(&settings);
+    let schedules = format!("{:?}", settings.all_schedules());
+    let Some(previous) = replace_settings(settings) else {
+        return;
+    };
+    let previous_schedules = format!("{:?}", previous.all_schedules());
+    if previous_schedules != schedules {
+        log::info!("Schedules changed from {} to {}", previous_schedules, schedules);
+    }
+    // Schedules, maintenance windows and quiet hours are read when the scheduler restarts
+    restart_scheduler();
+    for setting in restart_required(&previous, &current_settings()) {
+        log::warn!("Changes to {} take effect after a restart", setting);
+    }
+    log::info!("Configuration reloaded");
+}
+
+// Settings read once when the API server starts
+fn restart_required(previous: &Settings, settings: &Settings) -> Vec<&'static str> {
+    let (old, new) = (&previous.system, &settings.system);
+    let mut changed = Vec::new();
+    if old.listen_address != new.listen_address || old.port != new.port {
+        changed.push("system.listen_address and system.port");
+    }
+    if old.tls_cert != new.tls_cert || old.tls_key != new.tls_key {
+        changed.push("system.tls_cert and system.tls_key");
+    }
+    if old.base_path != new.base_path || old.static_dir != new.static_dir {
+        changed.push("system.base_path and system.static_dir");
+    }
+    if serde_json::to_value(&previous.auth).ok() != serde_json::to_value(&settings.auth).ok() {
+        changed.push("auth");
+    }
+    changed
+}
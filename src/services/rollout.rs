@@ -0,0 +1,370 @@
+use crate::config::{current_settings, parse_duration};
+use crate::kubernetes::client::Client;
+use crate::metrics::ROLLOUTS;
+use crate::models::models::Workload;
+use crate::notifications::ntfy::{notify_commit, notify_rollout};
+use crate::services::jobs::{JobHandle, ProgressKind};
+use k8s_openapi::api::apps::v1::{Deployment, StatefulSet};
+use k8s_openapi::api::core::v1::{Pod, PodTemplateSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use kube::ResourceExt;
+use oci_distribution::Reference;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time::Instant;
+
+// After an upgrade is pushed, the workload's Deployment or StatefulSet is watched until
+// its pods run the new version and are ready, so the notification reports what actually
+// happened in the cluster rather than the commit.
+
+// How often the Deployment or StatefulSet of an upgraded workload is checked
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// Container waiting reasons that do not resolve without another change
+const FAILED_REASONS: [&str; 5] = [
+    "ErrImagePull",
+    "ImagePullBackOff",
+    "InvalidImageName",
+    "CrashLoopBackOff",
+    "CreateContainerConfigError",
+];
+
+#[derive(Debug, PartialEq)]
+enum RolloutState {
+    Done,
+    Pending(String),
+    Failed(String),
+}
+
+// The parts of a Deployment or StatefulSet a rollout is followed by
+#[derive(Debug, Clone, PartialEq)]
+struct Controller {
+    kind: &'static str,
+    name: String,
+    generation: i64,
+    observed_generation: i64,
+    desired: i32,
+    replicas: i32,
+    updated: i32,
+    ready: i32,
+    template: PodTemplateSpec,
+    selector: String,
+}
+
+impl From<Deployment> for Controller {
+    fn from(deployment: Deployment) -> Self {
+        let name = deployment.name_any();
+        let spec = deployment.spec.unwrap_or_default();
+        let status = deployment.status.unwrap_or_default();
+        Controller {
+            kind: "Deployment",
+            name,
+            generation: deployment.metadata.generation.unwrap_or_default(),
+            observed_generation: status.observed_generation.unwrap_or_default(),
+            desired: spec.replicas.unwrap_or(1),
+            replicas: status.replicas.unwrap_or_default(),
+            updated: status.updated_replicas.unwrap_or_default(),
+            ready: status.ready_replicas.unwrap_or_default(),
+            template: spec.template,
+            selector: label_selector(&spec.selector),
+        }
+    }
+}
+
+impl From<StatefulSet> for Controller {
+    fn from(stateful_set: StatefulSet) -> Self {
+        let name = stateful_set.name_any();
+        let spec = stateful_set.spec.unwrap_or_default();
+        let status = stateful_set.status.unwrap_or_default();
+        Controller {
+            kind: "StatefulSet",
+            name,
+            generation: stateful_set.metadata.generation.unwrap_or_default(),
+            observed_generation: status.observed_generation.unwrap_or_default(),
+            desired: spec.replicas.unwrap_or(1),
+            replicas: status.replicas,
+            updated: status.updated_replicas.unwrap_or_default(),
+            ready: status.ready_replicas.unwrap_or_default(),
+            template: spec.template,
+            selector: label_selector(&spec.selector),
+        }
+    }
+}
+
+// A label selector in the form taken by the list API, e.g. `app=postgres,tier in (db)`
+fn label_selector(selector: &LabelSelector) -> String {
+    let mut terms: Vec<String> = selector
+        .match_labels
+        .iter()
+        .flatten()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    for expression in selector.match_expressions.iter().flatten() {
+        let values = expression.values.as_deref().unwrap_or_default().join(",");
+        terms.push(match expression.operator.as_str() {
+            "In" => format!("{} in ({})", expression.key, values),
+            "NotIn" => format!("{} notin ({})", expression.key, values),
+            "DoesNotExist" => format!("!{}", expression.key),
+            _ => expression.key.clone(),
+        });
+    }
+    terms.join(",")
+}
+
+// Registry and repository of an image, with its tag
+fn parse_image(image: &str) -> Option<(String, Option<String>)> {
+    let reference = Reference::try_from(image).ok()?;
+    let repository = format!("{}/{}", reference.registry(), reference.repository());
+    Some((repository, reference.tag().map(String::from)))
+}
+
+/// Whether the pods of a controller are the workload: watched with `slackwatch.enable`, with
+/// the workload's container first and running an image of the same repository.
+fn runs_workload(controller: &Controller, workload: &Workload) -> bool {
+    let template = &controller.template;
+    let enabled = template
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.annotations.as_ref())
+        .and_then(|annotations| annotations.get("slackwatch.enable"))
+        .is_some_and(|enabled| enabled == "true");
+    let container = template.spec.as_ref().and_then(|spec| spec.containers.first());
+    let repository = |image: &str| parse_image(image).map(|(repository, _)| repository);
+    enabled
+        && container.is_some_and(|container| {
+            let running = container.image.as_deref().and_then(repository);
+            container.name == workload.name && running.is_some() && running == repository(&workload.image)
+        })
+}
+
+/// Progress of an upgrade from the workload's controller and the pods its selector matches.
+/// The rollout is done once the controller runs the new version, has observed its latest
+/// spec and every desired replica is updated and ready with no old pods left.
+fn rollout_state(controller: &Controller, pods: &[Pod], workload: &Workload) -> RolloutState {
+    let target = format!("{} {}", controller.kind, controller.name);
+    let image = controller
+        .template
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.containers.iter().find(|container| container.name == workload.name))
+        .and_then(|container| container.image.as_deref());
+    let tag = image.and_then(parse_image).and_then(|(_, tag)| tag);
+    if tag.as_deref() != Some(workload.latest_version.as_str()) {
+        return RolloutState::Pending(format!("{} does not run {} yet", target, workload.latest_version));
+    }
+    for pod in pods {
+        if pod.metadata.deletion_timestamp.is_some() {
+            continue;
+        }
+        let waiting = pod
+            .status
+            .as_ref()
+            .and_then(|status| status.container_statuses.as_ref())
+            .and_then(|statuses| statuses.iter().find(|status| status.name == workload.name))
+            .and_then(|status| status.state.as_ref())
+            .and_then(|state| state.waiting.as_ref())
+            .and_then(|waiting| waiting.reason.as_deref());
+        if let Some(reason) = waiting.filter(|reason| FAILED_REASONS.contains(reason)) {
+            return RolloutState::Failed(format!("pod {} is in {}", pod.name_any(), reason));
+        }
+    }
+    if controller.observed_generation < controller.generation {
+        return RolloutState::Pending(format!("waiting for {} to pick up the new version", target));
+    }
+    let desired = controller.desired;
+    if controller.updated >= desired && controller.ready >= desired && controller.replicas <= desired {
+        RolloutState::Done
+    } else {
+        RolloutState::Pending(format!(
+            "{} of {} replicas of {} updated, {} ready",
+            controller.updated, desired, target, controller.ready
+        ))
+    }
+}
+
+// The Deployment or StatefulSet running the workload, looked up by its pod template
+async fn find_controller(client: &Client, workload: &Workload) -> Result<Controller, String> {
+    let namespace = &workload.namespace;
+    let deployments = client
+        .list_deployments(namespace)
+        .await
+        .map_err(|e| format!("failed to list deployments: {}", e))?;
+    let stateful_sets = client
+        .list_stateful_sets(namespace)
+        .await
+        .map_err(|e| format!("failed to list statefulsets: {}", e))?;
+    let mut controllers: Vec<Controller> = deployments
+        .into_iter()
+        .map(Controller::from)
+        .chain(stateful_sets.into_iter().map(Controller::from))
+        .filter(|controller| runs_workload(controller, workload))
+        .collect();
+    match controllers.len() {
+        1 => Ok(controllers.remove(0)),
+        0 => Err(format!("no Deployment or StatefulSet in {} runs {}", namespace, workload.name)),
+        _ => Err(format!(
+            "several controllers in {} run {}: {}",
+            namespace,
+            workload.name,
+            controllers.iter().map(|c| format!("{} {}", c.kind, c.name)).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+async fn check_rollout(client: &Client, controller: &Controller, workload: &Workload) -> Result<RolloutState, String> {
+    let namespace = &workload.namespace;
+    let controller: Controller = match controller.kind {
+        "StatefulSet" => client.get_stateful_set(namespace, &controller.name).await.map(Controller::from),
+        _ => client.get_deployment(namespace, &controller.name).await.map(Controller::from),
+    }
+    .map_err(|e| format!("failed to get {} {}: {}", controller.kind, controller.name, e))?;
+    let pods = client
+        .list_selected_pods(namespace, &controller.selector)
+        .await
+        .map_err(|e| format!("failed to list pods of {} {}: {}", controller.kind, controller.name, e))?;
+    Ok(rollout_state(&controller, &pods, workload))
+}
+
+async fn wait_for_rollout(workload: &Workload, job: &JobHandle, timeout: chrono::Duration) -> Result<(), String> {
+    let client = Client::new()
+        .await
+        .map_err(|e| format!("Failed to connect to Kubernetes: {}", e))?;
+    let controller = find_controller(&client, workload).await?;
+    log::info!("Following the rollout of {} {}", controller.kind, controller.name);
+    let deadline = Instant::now() + timeout.to_std().unwrap_or_default();
+    let mut last_status = String::new();
+    loop {
+        let state = check_rollout(&client, &controller, workload)
+            .await
+            .unwrap_or_else(RolloutState::Pending);
+        match state {
+            RolloutState::Done => return Ok(()),
+            RolloutState::Failed(reason) => return Err(reason),
+            RolloutState::Pending(status) if status != last_status => {
+                job.emit(ProgressKind::Rollout {
+                    namespace: workload.namespace.clone(),
+                    name: workload.name.clone(),
+                    status: status.clone(),
+                });
+                last_status = status;
+            }
+            RolloutState::Pending(_) => {}
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("timed out after {}, {}", current_settings().system.rollout_timeout, last_status));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Watch a workload whose upgrade was pushed until it rolls out, fails or
+/// `system.rollout_timeout` passes, then notify the outcome. Returns the job result.
+pub async fn verify_rollout(workload: &Workload, job: &JobHandle) -> Result<Value, String> {
+    let target = format!("{}/{}", workload.namespace, workload.name);
+    let timeout = parse_duration(&current_settings().system.rollout_timeout).unwrap_or_else(|e| {
+        log::error!("Invalid rollout_timeout, waiting 15 minutes: {}", e);
+        chrono::Duration::minutes(15)
+    });
+    if timeout <= chrono::Duration::zero() {
+        notify_commit(workload)
+            .await
+            .unwrap_or_else(|e| log::error!("Error sending notification: {}", e));
+        return Ok(json!({ "upgraded": target, "version": workload.latest_version }));
+    }
+
+    log::info!("Waiting up to {} for {} to roll out {}", timeout, target, workload.latest_version);
+    let outcome = wait_for_rollout(workload, job, timeout).await;
+    match &outcome {
+        Ok(()) => log::info!("{} rolled out {}", target, workload.latest_version),
+        Err(e) => log::error!("Rollout of {} to {} failed: {}", target, workload.latest_version, e),
+    }
+    ROLLOUTS
+        .with_label_values(&[if outcome.is_ok() { "success" } else { "failure" }])
+        .inc();
+    notify_rollout(workload, &outcome)
+        .await
+        .unwrap_or_else(|e| log::error!("Error sending notification: {}", e));
+    outcome
+        .map(|_| json!({ "upgraded": target, "version": workload.latest_version, "rollout": "succeeded" }))
+        .map_err(|e| format!("Rollout of {} failed: {}", target, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::models::UpdateStatus;
+
+    fn pod(name: &str, image: &str, waiting: Option<&str>) -> Pod {
+        serde_json::from_value(json!({
+            "metadata": { "name": name, "namespace": "default", "labels": { "app": "postgres" } },
+            "spec": { "containers": [{ "name": "postgres", "image": image }] },
+            "status": { "containerStatuses": [{
+                "name": "postgres",
+                "image": image,
+                "imageID": "",
+                "ready": waiting.is_none(),
+                "restartCount": 0,
+                "state": waiting.map_or(json!({ "running": {} }), |reason| json!({ "waiting": { "reason": reason } })),
+            }] },
+        }))
+        .unwrap()
+    }
+
+    fn deployment(image: &str, generation: i64, status: Value) -> Controller {
+        let deployment: Deployment = serde_json::from_value(json!({
+            "metadata": { "name": "postgres", "namespace": "default", "generation": generation },
+            "spec": {
+                "replicas": 2,
+                "selector": {
+                    "matchLabels": { "app": "postgres" },
+                    "matchExpressions": [{ "key": "tier", "operator": "In", "values": ["db", "cache"] }],
+                },
+                "template": {
+                    "metadata": { "labels": { "app": "postgres" }, "annotations": { "slackwatch.enable": "true" } },
+                    "spec": { "containers": [{ "name": "postgres", "image": image }] },
+                },
+            },
+            "status": status,
+        }))
+        .unwrap();
+        Controller::from(deployment)
+    }
+
+    #[test]
+    fn test_rollout_state() {
+        let workload = Workload {
+            git_ops_repo: Some("homelab".to_string()),
+            update_available: UpdateStatus::Available,
+            ..Workload::for_test("postgres", "postgres:16.1.0", "16.1.0", "16.2.0")
+        };
+        let old = deployment("postgres:16.1.0", 1, json!({ "observedGeneration": 1, "replicas": 2, "updatedReplicas": 2, "readyReplicas": 2 }));
+        assert!(runs_workload(&old, &workload));
+        assert_eq!(old.selector, "app=postgres,tier in (db,cache)");
+        assert!(!runs_workload(&old, &Workload::for_test("postgres", "postgres-exporter:0.15.0", "0.15.0", "")));
+        assert!(!runs_workload(&old, &Workload::for_test("redis", "redis:7.2.0", "7.2.0", "")));
+        assert_eq!(
+            rollout_state(&old, &[], &workload),
+            RolloutState::Pending("Deployment postgres does not run 16.2.0 yet".to_string())
+        );
+
+        let unobserved = deployment("postgres:16.2.0", 2, json!({ "observedGeneration": 1, "replicas": 2, "updatedReplicas": 2, "readyReplicas": 2 }));
+        assert!(matches!(rollout_state(&unobserved, &[], &workload), RolloutState::Pending(_)));
+        let rolling = deployment("postgres:16.2.0", 2, json!({ "observedGeneration": 2, "replicas": 3, "updatedReplicas": 1, "readyReplicas": 2 }));
+        let pods = [
+            pod("postgres-a", "postgres:16.1.0", None),
+            pod("postgres-b", "postgres:16.1.0", None),
+            pod("postgres-c", "docker.io/library/postgres:16.2.0", Some("ContainerCreating")),
+        ];
+        assert_eq!(
+            rollout_state(&rolling, &pods, &workload),
+            RolloutState::Pending("1 of 2 replicas of Deployment postgres updated, 2 ready".to_string())
+        );
+        let crashing = [pod("postgres-c", "postgres:16.2.0", Some("CrashLoopBackOff"))];
+        assert_eq!(
+            rollout_state(&rolling, &crashing, &workload),
+            RolloutState::Failed("pod postgres-c is in CrashLoopBackOff".to_string())
+        );
+        let done = deployment("postgres:16.2.0", 2, json!({ "observedGeneration": 2, "replicas": 2, "updatedReplicas": 2, "readyReplicas": 2 }));
+        assert_eq!(rollout_state(&done, &[], &workload), RolloutState::Done);
+    }
+}
@@ -1,68 +1,325 @@
-use crate::config::Settings;
-use crate::services::workloads::fetch_and_update_all_watched;
+use crate::config::{current_settings, Settings};
+use crate::models::models::JobType;
+use crate::database::client::next_deferred_notification;
+use crate::notifications::ntfy::send_deferred;
+use crate::services::auto_upgrade::{has_deferred, run_auto_upgrades, take_deferred};
+use crate::services::windows::next_maintenance_window;
+use crate::services::jobs::{scan_running, start_job, JobError};
+use crate::services::workloads::{fetch_and_update_all_watched, fetch_and_update_scheduled};
 use config::ConfigError;
 use cron::Schedule;
+use std::collections::BTreeSet;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{LazyLock, Mutex};
 use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
 use tokio::time::{sleep_until, Instant as TokioInstant};
+use crate::metrics::SCHEDULER_HEARTBEAT;
 
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
 
+static LAST_HEARTBEAT: AtomicI64 = AtomicI64::new(0);
+static SCHEDULER_TASK: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+static SCHEDULE_CHANGED: LazyLock<Notify> = LazyLock::new(Notify::new);
 
-pub async fn scheduler(schedule: &Schedule) {
-    log::info!("Scheduler started");
-    println!("Cron schedule: {}", schedule);
-    log::info!("Cron schedule: {}", schedule);
-    let mut i = 0;
-    for datetime in schedule.upcoming(chrono::Utc) {
-        if i < 5 {
-            println!("Next scheduled time: {}", datetime);
-            i += 1;
-        } else {
-            break;
+#[derive(Debug, Serialize)]
+pub struct ScheduleOverview {
+    pub name: String,
+    pub cron: String,
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+fn heartbeat() {
+    let now = Utc::now().timestamp();
+    LAST_HEARTBEAT.store(now, Ordering::Relaxed);
+    SCHEDULER_HEARTBEAT.set(now);
+}
+
+/// Last time the scheduler loop was seen running, None before the first tick.
+pub fn last_heartbeat() -> Option<DateTime<Utc>> {
+    match LAST_HEARTBEAT.load(Ordering::Relaxed) {
+        0 => None,
+        ts => DateTime::from_timestamp(ts, 0),
+    }
+}
+
+/// Whether the scheduler task has been started and has not exited or panicked.
+pub fn scheduler_alive() -> bool {
+    SCHEDULER_TASK
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|task| !task.is_finished())
+}
+
+pub fn spawn_scheduler(settings: Settings) {
+    let task = tokio::task::spawn(run_scheduler(settings));
+    *SCHEDULER_TASK.lock().unwrap() = Some(task);
+}
+
+/// Restart the scheduler with the current schedules, held notifications and deferred
+/// upgrades. A scan that is running finishes first.
+pub fn restart_scheduler() {
+    SCHEDULE_CHANGED.notify_one();
+}
+
+// Schedules that came due while a scan was running, scanned once it finishes
+static QUEUED_SCHEDULES: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Called when a scan finishes, so schedules queued behind it are scanned right away.
+pub fn scan_finished() {
+    if !QUEUED_SCHEDULES.lock().unwrap().is_empty() {
+        restart_scheduler();
+    }
+}
+
+// What the scheduler wakes up for
+#[derive(Debug, PartialEq)]
+enum Wake {
+    Scan(Vec<String>),
+    Queued,
+    MaintenanceWindow,
+    QuietHoursEnd,
+}
+
+/// The earliest of the next scheduled scan, the next maintenance window when upgrades wait
+/// for one and the end of quiet hours when notifications are held.
+fn next_wake(schedules: &[(String, Schedule)], now: DateTime<Utc>) -> Option<(DateTime<Utc>, Wake)> {
+    let mut wakes: Vec<(DateTime<Utc>, Wake)> = Vec::new();
+    if !scan_running() && !QUEUED_SCHEDULES.lock().unwrap().is_empty() {
+        wakes.push((now, Wake::Queued));
+    }
+    if let Some((next, due)) = next_due(schedules, now) {
+        wakes.push((next, Wake::Scan(due)));
+    }
+    if has_deferred() {
+        if let Some(opens) = next_maintenance_window(&current_settings(), now) {
+            wakes.push((opens, Wake::MaintenanceWindow));
         }
     }
+    match next_deferred_notification() {
+        Ok(Some(send_after)) => match DateTime::parse_from_rfc3339(&send_after) {
+            Ok(send_after) => wakes.push((send_after.with_timezone(&Utc), Wake::QuietHoursEnd)),
+            Err(e) => log::error!("Invalid time {} of a held notification: {}", send_after, e),
+        },
+        Ok(None) => {}
+        Err(e) => log::error!("Failed to look up held notifications: {}", e),
+    }
+    wakes.into_iter().min_by_key(|(at, _)| *at)
+}
+
+/// Parse every named schedule in the settings, logging and skipping invalid ones.
+fn parse_schedules(settings: &Settings) -> Vec<(String, Schedule)> {
+    settings
+        .all_schedules()
+        .into_iter()
+        .filter_map(|(name, cron)| match Schedule::from_str(cron) {
+            Ok(schedule) => Some((name.to_string(), schedule)),
+            Err(e) => {
+                log::error!("Invalid schedule {} ({}): {}", name, cron, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The earliest time after `after` any schedule fires, with the names of every schedule due then.
+fn next_due(schedules: &[(String, Schedule)], after: DateTime<Utc>) -> Option<(DateTime<Utc>, Vec<String>)> {
+    let upcoming: Vec<(&str, DateTime<Utc>)> = schedules
+        .iter()
+        .filter_map(|(name, schedule)| schedule.after(&after).next().map(|next| (name.as_str(), next)))
+        .collect();
+    let next = upcoming.iter().map(|(_, next)| *next).min()?;
+    let due = upcoming
+        .into_iter()
+        .filter(|(_, time)| *time == next)
+        .map(|(name, _)| name.to_string())
+        .collect();
+    Some((next, due))
+}
+
+pub async fn scheduler(schedules: &[(String, Schedule)]) {
+    log::info!("Scheduler started");
+    for (name, schedule) in schedules {
+        log::info!("Cron schedule {}: {}", name, schedule);
+    }
     let now = chrono::Utc::now();
-    if let Some(next) = schedule.upcoming(chrono::Utc).next() {
-        let duration_until_next = (next - now).to_std().expect("Failed to calculate duration");
-        println!("Next scheduled time: {:?}", next);
+    if let Some((next, wake)) = next_wake(schedules, now) {
+        // Zero when the next time has passed while getting here
+        let duration_until_next = (next - now).to_std().unwrap_or_default();
+        log::info!("Next scheduled time: {} for {:?}", next, wake);
         // Convert std::time::Instant to tokio::time::Instant
         let tokio_now = TokioInstant::now();
         let tokio_future = tokio_now + duration_until_next;
-        // Sleep until the next scheduled time
-        sleep_until(tokio_future).await;
-        // Execute your function
-        refresh_all_workloads().await;
+        // Sleep until the next scheduled time, waking up regularly to record a heartbeat
+        while TokioInstant::now() < tokio_future {
+            heartbeat();
+            tokio::select! {
+                _ = sleep_until(tokio_future.min(TokioInstant::now() + HEARTBEAT_INTERVAL)) => {}
+                _ = SCHEDULE_CHANGED.notified() => {
+                    log::info!("Schedule changed, restarting scheduler");
+                    return;
+                }
+            }
+        }
+        heartbeat();
+        let started = Utc::now();
+        match wake {
+            Wake::Scan(due) => refresh_workloads(Some(due)).await,
+            Wake::Queued => {
+                let queued: Vec<String> = std::mem::take(&mut *QUEUED_SCHEDULES.lock().unwrap()).into_iter().collect();
+                log::info!("Previous scan finished, scanning queued schedules {}", queued.join(", "));
+                refresh_workloads(Some(queued)).await
+            }
+            // Scan the workloads again so upgrades start from their current state
+            Wake::MaintenanceWindow => {
+                log::info!("Maintenance window opened, scanning for deferred upgrades");
+                refresh_workloads(Some(take_deferred())).await
+            }
+            Wake::QuietHoursEnd => send_deferred().await,
+        }
+        queue_missed(schedules, started);
+    }
+}
+
+/// The schedules that fired between `started` and `finished`, while the scheduler was busy.
+fn due_between(schedules: &[(String, Schedule)], started: DateTime<Utc>, finished: DateTime<Utc>) -> Vec<String> {
+    schedules
+        .iter()
+        .filter(|(_, schedule)| schedule.after(&started).next().is_some_and(|next| next <= finished))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+// Queue the schedules that came due while the scheduler ran something, its own scan included
+fn queue_missed(schedules: &[(String, Schedule)], started: DateTime<Utc>) {
+    let missed = due_between(schedules, started, Utc::now());
+    if !missed.is_empty() {
+        log::info!("Schedules {} came due during the last run, queueing them", missed.join(", "));
+        QUEUED_SCHEDULES.lock().unwrap().extend(missed);
     }
 }
 
 pub async fn run_scheduler(settings: Settings) {
+    heartbeat();
     //Load Scheduler
     let run_at_startup = settings.system.run_at_startup;
     if run_at_startup {
         log::info!("Running full refresh at startup");
-        refresh_all_workloads().await;
+        let started = Utc::now();
+        refresh_workloads(None).await;
+        queue_missed(&parse_schedules(&current_settings()), started);
     }
-    let schedule_str = settings.system.schedule;
-    log::info!("Schedule is {}", schedule_str);
-    let schedule = &Schedule::from_str(&schedule_str).expect("Failed to parse cron expression");
     loop {
-        scheduler(schedule).await;
+        // Read on every run so reloaded schedules take effect
+        let schedules = parse_schedules(&current_settings());
+        if schedules.is_empty() {
+            // Validation keeps invalid schedules out, wait for a fixed configuration just in case
+            log::error!("No valid schedule, waiting for a configuration reload");
+            heartbeat();
+            let _ = tokio::time::timeout(HEARTBEAT_INTERVAL, SCHEDULE_CHANGED.notified()).await;
+            continue;
+        }
+        scheduler(&schedules).await;
     }
 }
 
-pub async fn next_schedule_time(schedule_str: &String) -> String {
-    let now = chrono::Utc::now();
-    let schedule = &Schedule::from_str(&schedule_str).expect("Failed to parse cron expression");
-    if let Some(next) = schedule.upcoming(chrono::Utc).next() {
-        let duration_until_next = (next - now).to_std().expect("Failed to calculate duration");
-        return format!("{:?}", next);
+/// The next run of every named schedule in the settings.
+pub fn schedule_overview(settings: &Settings) -> Vec<ScheduleOverview> {
+    settings
+        .all_schedules()
+        .into_iter()
+        .map(|(name, cron)| ScheduleOverview {
+            name: name.to_string(),
+            cron: cron.to_string(),
+            next_run: Schedule::from_str(cron)
+                .ok()
+                .and_then(|schedule| schedule.upcoming(chrono::Utc).next()),
+        })
+        .collect()
+}
+
+/// The time of the next scheduled scan across all schedules.
+pub fn next_run(settings: &Settings) -> Option<DateTime<Utc>> {
+    next_due(&parse_schedules(settings), Utc::now()).map(|(next, _)| next)
+}
+
+/// Scan the workloads on the `due` schedules, or every workload when None.
+async fn refresh_workloads(due: Option<Vec<String>>) {
+    let target = due.as_ref().map(|due| due.join(","));
+    log::info!("Refreshing workloads on schedules {}", target.as_deref().unwrap_or("all"));
+    let job = match start_job(JobType::ScanAll, target.as_deref()) {
+        Ok(job) => job,
+        Err(JobError::AlreadyRunning(id)) => {
+            match due {
+                Some(due) => {
+                    log::info!("Job {} is still running, queueing schedules {}", id, due.join(", "));
+                    QUEUED_SCHEDULES.lock().unwrap().extend(due);
+                    // The scan may have finished in the meantime
+                    scan_finished();
+                }
+                None => log::info!("Skipping full scan, job {} is still running", id),
+            }
+            return;
+        }
+        Err(e) => {
+            log::error!("Error starting scan: {}", e);
+            return;
+        }
+    };
+    let result = match &due {
+        Some(due) => fetch_and_update_scheduled(&job, due).await,
+        None => fetch_and_update_all_watched(&job, false).await,
+    };
+    if let Err(e) = &result {
+        log::error!("Error refreshing workloads: {}", e);
+    }
+    job.finish(result.as_ref().map(|_| job.summary()).map_err(Clone::clone));
+    if let Ok(updates) = result {
+        run_auto_upgrades(&updates).await;
     }
-    "No upcoming schedule".to_string()
 }
 
-async fn refresh_all_workloads() {
-    log::info!("Refreshing all workloads");
-    fetch_and_update_all_watched()
-        .await
-        .unwrap_or_else(|e| log::error!("Error refreshing workloads: {}", e));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_due() {
+        let schedule = |name: &str, cron: &str| (name.to_string(), Schedule::from_str(cron).unwrap());
+        let schedules = vec![
+            schedule("default", "0 0 * * * *"),
+            schedule("daily", "0 0 0 * * *"),
+            schedule("weekly", "0 0 0 * * Mon"),
+        ];
+        let after = DateTime::parse_from_rfc3339("2024-01-07T22:30:00Z").unwrap().with_timezone(&Utc);
+        let (next, due) = next_due(&schedules, after).unwrap();
+        assert_eq!(next.to_rfc3339(), "2024-01-07T23:00:00+00:00");
+        assert_eq!(due, vec!["default"]);
+
+        // 2024-01-08 is a Monday, all three are due at midnight
+        let (next, due) = next_due(&schedules, next).unwrap();
+        assert_eq!(next.to_rfc3339(), "2024-01-08T00:00:00+00:00");
+        assert_eq!(due, vec!["default", "daily", "weekly"]);
+        assert!(next_due(&[], after).is_none());
+    }
+
+    #[test]
+    fn test_due_between() {
+        let schedule = |name: &str, cron: &str| (name.to_string(), Schedule::from_str(cron).unwrap());
+        let schedules = vec![
+            schedule("default", "0 0 * * * *"),
+            schedule("daily", "0 0 0 * * *"),
+            schedule("weekly", "0 0 0 * * Mon"),
+        ];
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        // A scan started on the hour runs past midnight, the Monday schedules fired meanwhile
+        let missed = due_between(&schedules, at("2024-01-07T23:00:00Z"), at("2024-01-08T00:20:00Z"));
+        assert_eq!(missed, vec!["default", "daily", "weekly"]);
+        assert!(due_between(&schedules, at("2024-01-07T23:00:00Z"), at("2024-01-07T23:40:00Z")).is_empty());
+    }
 }
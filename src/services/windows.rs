@@ -0,0 +1,245 @@
+use crate::config::{parse_duration, Settings, TimeWindow};
+use chrono::{DateTime, Datelike, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use cron::Schedule;
+use ntfy::Priority;
+use std::str::FromStr;
+
+// Maintenance windows gate automatic upgrades and quiet hours hold notifications. The
+// scheduler wakes up when a window opens or closes to run what was held back.
+
+// How far ahead openings are looked for, a week covers every weekday
+const HORIZON_DAYS: i64 = 8;
+
+// Openings of a cron window looked at, for expressions that fire every minute
+const MAX_OPENINGS: usize = 1024;
+
+// When a window opens and closes
+type Opening = (DateTime<Utc>, DateTime<Utc>);
+
+enum Recurrence {
+    Days {
+        days: Vec<Weekday>,
+        start: NaiveTime,
+        end: NaiveTime,
+    },
+    Cron {
+        schedule: Box<Schedule>,
+        duration: chrono::Duration,
+    },
+}
+
+// A local time skipped by a DST change is taken an hour later
+fn from_local(timezone: &Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    timezone
+        .from_local_datetime(&local)
+        .earliest()
+        .or_else(|| timezone.from_local_datetime(&(local + chrono::Duration::hours(1))).earliest())
+        .map(|at| at.with_timezone(&Utc))
+}
+
+impl TimeWindow {
+    fn parse(&self) -> Result<(Recurrence, Tz), String> {
+        let timezone = match &self.timezone {
+            Some(name) => Tz::from_str(name).map_err(|_| format!("unknown timezone {:?}", name))?,
+            None => Tz::UTC,
+        };
+        let recurrence = match (&self.start, &self.end, &self.cron, &self.duration) {
+            (Some(start), Some(end), None, None) => {
+                let days = self
+                    .days
+                    .iter()
+                    .map(|day| Weekday::from_str(day).map_err(|_| format!("invalid day {:?}", day)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let time = |value: &str| {
+                    NaiveTime::parse_from_str(value, "%H:%M")
+                        .map_err(|_| format!("invalid time {:?}, expected HH:MM", value))
+                };
+                Recurrence::Days {
+                    days,
+                    start: time(start)?,
+                    end: time(end)?,
+                }
+            }
+            (None, None, Some(cron), Some(duration)) => {
+                if !self.days.is_empty() {
+                    return Err("days cannot be combined with cron, use its day of week field".to_string());
+                }
+                let schedule = Schedule::from_str(cron)
+                    .map_err(|e| format!("invalid cron expression {:?}: {}", cron, e))?;
+                let duration = parse_duration(duration)?;
+                if duration <= chrono::Duration::zero() {
+                    return Err("duration must be longer than zero".to_string());
+                }
+                Recurrence::Cron {
+                    schedule: Box::new(schedule),
+                    duration,
+                }
+            }
+            _ => return Err("expected start and end, or cron and duration".to_string()),
+        };
+        Ok((recurrence, timezone))
+    }
+
+    /// Check that the window can be evaluated.
+    pub fn check(&self) -> Result<(), String> {
+        self.parse().map(|_| ())
+    }
+
+    /// Openings of the window that have not closed at `from`, by start time, up to a week ahead.
+    fn openings(&self, from: DateTime<Utc>) -> Result<Vec<Opening>, String> {
+        let (recurrence, timezone) = self.parse()?;
+        let mut openings = Vec::new();
+        match recurrence {
+            Recurrence::Days { days, start, end } => {
+                let today = from.with_timezone(&timezone).date_naive();
+                // From the day before, for a range running past midnight
+                for offset in -1..HORIZON_DAYS {
+                    let date = today + chrono::Duration::days(offset);
+                    if !days.is_empty() && !days.contains(&date.weekday()) {
+                        continue;
+                    }
+                    let end_date = if end <= start { date + chrono::Duration::days(1) } else { date };
+                    let opens = from_local(&timezone, date.and_time(start));
+                    let closes = from_local(&timezone, end_date.and_time(end));
+                    if let (Some(opens), Some(closes)) = (opens, closes) {
+                        openings.push((opens, closes));
+                    }
+                }
+            }
+            Recurrence::Cron { schedule, duration } => {
+                let horizon = from + chrono::Duration::days(HORIZON_DAYS);
+                let since = (from - duration).with_timezone(&timezone);
+                for opens in schedule.after(&since).take(MAX_OPENINGS) {
+                    let opens = opens.with_timezone(&Utc);
+                    if opens > horizon {
+                        break;
+                    }
+                    openings.push((opens, opens + duration));
+                }
+            }
+        }
+        openings.retain(|(_, closes)| *closes > from);
+        Ok(openings)
+    }
+
+    /// Whether the window is open at `at`.
+    pub fn contains(&self, at: DateTime<Utc>) -> Result<bool, String> {
+        Ok(self.openings(at)?.iter().any(|(opens, _)| *opens <= at))
+    }
+
+    /// When the window open at `at` closes, joining overlapping openings. None when it is closed.
+    pub fn closes_after(&self, at: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, String> {
+        let mut closes: Option<DateTime<Utc>> = None;
+        for (opening, closing) in self.openings(at)? {
+            match closes {
+                None if opening <= at => closes = Some(closing),
+                Some(current) if opening <= current => closes = Some(current.max(closing)),
+                _ => {}
+            }
+        }
+        Ok(closes)
+    }
+
+    /// When the window next opens, `at` itself when it is open.
+    pub fn opens_after(&self, at: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, String> {
+        Ok(self.openings(at)?.first().map(|(opens, _)| (*opens).max(at)))
+    }
+}
+
+/// Whether automatic upgrades may start at `at`, which is always when no maintenance window is set.
+pub fn in_maintenance_window(settings: &Settings, at: DateTime<Utc>) -> Result<bool, String> {
+    let mut windows = settings.all_maintenance_windows().peekable();
+    if windows.peek().is_none() {
+        return Ok(true);
+    }
+    for window in windows {
+        if window.contains(at)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// When the next maintenance window opens after `at`.
+pub fn next_maintenance_window(settings: &Settings, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    settings
+        .all_maintenance_windows()
+        .filter_map(|window| window.opens_after(at).ok().flatten())
+        .min()
+}
+
+/// When the quiet hours a notification of `priority` sent at `at` falls in end, None when
+/// it can be sent right away.
+pub fn quiet_until(settings: &Settings, priority: Priority, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let quiet_hours = settings.notifications.as_ref()?.quiet_hours.as_ref()?;
+    if priority >= quiet_hours.urgent_priority {
+        return None;
+    }
+    quiet_hours
+        .window
+        .closes_after(at)
+        .inspect_err(|e| log::error!("Invalid quiet hours, sending notifications right away: {}", e))
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_days_window() {
+        let window = TimeWindow {
+            days: vec!["Sat".to_string()],
+            start: Some("22:00".to_string()),
+            end: Some("04:00".to_string()),
+            timezone: Some("Europe/Berlin".to_string()),
+            ..TimeWindow::default()
+        };
+        // 2024-01-06 is a Saturday, Berlin is an hour ahead of UTC in winter
+        assert_eq!(window.contains(at("2024-01-06T22:30:00Z")), Ok(true));
+        assert_eq!(window.contains(at("2024-01-07T02:59:00Z")), Ok(true));
+        assert_eq!(window.contains(at("2024-01-07T03:00:00Z")), Ok(false));
+        assert_eq!(window.contains(at("2024-01-06T20:30:00Z")), Ok(false));
+        assert_eq!(window.closes_after(at("2024-01-06T22:30:00Z")), Ok(Some(at("2024-01-07T03:00:00Z"))));
+        assert_eq!(window.opens_after(at("2024-01-07T12:00:00Z")), Ok(Some(at("2024-01-13T21:00:00Z"))));
+    }
+
+    #[test]
+    fn test_cron_window() {
+        let window = TimeWindow {
+            cron: Some("0 0 2 * * Mon-Fri".to_string()),
+            duration: Some("3h".to_string()),
+            ..TimeWindow::default()
+        };
+        // 2024-01-05 is a Friday
+        assert_eq!(window.contains(at("2024-01-05T04:59:00Z")), Ok(true));
+        assert_eq!(window.closes_after(at("2024-01-05T03:00:00Z")), Ok(Some(at("2024-01-05T05:00:00Z"))));
+        assert_eq!(window.closes_after(at("2024-01-05T05:00:00Z")), Ok(None));
+        assert_eq!(window.opens_after(at("2024-01-05T06:00:00Z")), Ok(Some(at("2024-01-08T02:00:00Z"))));
+        assert!(TimeWindow { days: vec!["Mon".to_string()], ..window }.check().is_err());
+    }
+
+    #[test]
+    fn test_auto_upgrade_maintenance_window() {
+        // The single window read before `maintenance_windows` still gates upgrades
+        let settings: Settings = config::Config::builder()
+            .add_source(config::File::from_str(
+                "[auto_upgrade]\nmaintenance_window = { days = [\"Sat\"], start = \"02:00\", end = \"06:00\" }",
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert!(settings.validate().is_ok());
+        assert_eq!(in_maintenance_window(&settings, at("2024-01-06T03:00:00Z")), Ok(true));
+        assert_eq!(in_maintenance_window(&settings, at("2024-01-06T12:00:00Z")), Ok(false));
+        assert_eq!(next_maintenance_window(&settings, at("2024-01-06T12:00:00Z")), Some(at("2024-01-13T02:00:00Z")));
+    }
+}
@@ -1,120 +1,508 @@
+use crate::config::{current_settings, parse_duration, System};
 use crate::database;
 use crate::database::client::get_latest_scan_id;
 use crate::kubernetes::client::{find_enabled_workloads, find_specific_workload};
-use crate::models::models::{UpdateStatus, Workload};
+use crate::metrics::{LAST_SUCCESSFUL_SCAN, SCAN_DURATION};
+use crate::models::models::{FailureKind, ImageMetadata, ImageTag, UpdateLevel, UpdateStatus, Workload};
+use crate::notifications::failures::{report_failure, report_success};
 use crate::notifications::ntfy::send_notification;
-use crate::repocheck::repocheck::get_tags_for_image;
+use crate::services::changelog::attach_changelog;
+use crate::services::ignores::apply_ignores;
+use crate::services::jobs::{JobHandle, ProgressKind};
+use crate::repocheck::repocheck::{
+    get_image_metadata, get_tag_architectures, get_tag_created, get_tags_for_image, is_auth_error,
+    repository_of,
+};
+use crate::repocheck::backends::sort_newest_first;
+use futures::StreamExt;
+use std::collections::HashMap;
+use kube::Error as KubeError;
 use regex::Regex;
 use semver::Version;
 
-pub async fn update_single_workload(current_workload: Workload) -> Result<(), String> {
-    let workload = find_specific_workload(
-        &current_workload.name.clone(),
-        &current_workload.namespace.clone(),
-    )
-    .await
-    .map_err(|e| e.to_string())?;
+#[derive(Debug)]
+pub enum WorkloadError {
+    NotFound(String),
+    Kubernetes(String),
+    Registry(String),
+    Database(String),
+    /// An include or exclude pattern of the workload does not compile
+    InvalidAnnotation(String),
+}
+
+impl std::fmt::Display for WorkloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorkloadError::NotFound(e) => write!(f, "Workload not found: {}", e),
+            WorkloadError::Kubernetes(e) => write!(f, "Kubernetes error: {}", e),
+            WorkloadError::Registry(e) => write!(f, "Registry error: {}", e),
+            WorkloadError::Database(e) => write!(f, "Database error: {}", e),
+            WorkloadError::InvalidAnnotation(e) => write!(f, "Invalid annotation: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WorkloadError {}
+
+/// Look up the live state of a workload in the cluster.
+pub async fn find_workload(current_workload: &Workload) -> Result<Workload, WorkloadError> {
+    let workload = find_specific_workload(&current_workload.name, &current_workload.namespace)
+        .await
+        .map_err(|e| match e {
+            KubeError::Api(response) if response.code == 404 => WorkloadError::NotFound(format!(
+                "{}/{}",
+                current_workload.namespace, current_workload.name
+            )),
+            e => WorkloadError::Kubernetes(e.to_string()),
+        })?;
     log::info!("Found workload: {:?}", workload);
+    Ok(workload)
+}
+
+pub async fn update_single_workload(
+    workload: Workload,
+    job: &JobHandle,
+    bypass_cache: bool,
+) -> Result<(), WorkloadError> {
+    job.emit(ProgressKind::Started {
+        namespace: workload.namespace.clone(),
+        name: workload.name.clone(),
+        image: workload.image.clone(),
+    });
+    if let Err(e) = check_patterns(&workload).await {
+        return Err(WorkloadError::InvalidAnnotation(e));
+    }
     let scan_id = get_latest_scan_id().unwrap_or(0) + 1;
-    if let Some(latest_tag) = find_latest_tag_for_image(&workload).await {
-        let workload = parse_tags(&workload).await.map_err(|e| e.to_string())?;
+    let repository = repository_of(&workload.image).map_err(WorkloadError::Registry)?;
+    let tags = fetch_tags(&repository, bypass_cache)
+        .await
+        .map_err(WorkloadError::Registry)?;
+    let workload = if !tags.is_empty() {
+        job.emit(ProgressKind::TagsFetched {
+            namespace: workload.namespace.clone(),
+            name: workload.name.clone(),
+            count: tags.len(),
+        });
+        let workload = evaluate_workload(workload, tags, &load_system()).await;
 
-        if workload.update_available.to_string() == "Available" {
+        if workload.update_available == UpdateStatus::Available {
+            job.emit(ProgressKind::UpdateFound {
+                namespace: workload.namespace.clone(),
+                name: workload.name.clone(),
+                current_version: workload.current_version.clone(),
+                latest_version: workload.latest_version.clone(),
+            });
             send_notification(&workload)
                 .await
                 .unwrap_or_else(|e| log::error!("Error sending notification: {}", e));
         }
-        std::thread::spawn(move || database::client::insert_workload(&workload, scan_id))
-            .join()
-            .map_err(|_| "Thread error".to_string())?
-            .expect("TODO: panic message");
+        workload
     } else {
         log::info!("No tags found for image: {}", workload.image);
-        std::thread::spawn(move || database::client::insert_workload(&workload, scan_id))
-            .join()
-            .map_err(|_| "Thread error".to_string())?
-            .expect("TODO: panic message");
-    }
+        workload
+    };
+    let (namespace, name) = (workload.namespace.clone(), workload.name.clone());
+    std::thread::spawn(move || database::client::insert_workload(&workload, scan_id))
+        .join()
+        .map_err(|_| WorkloadError::Database("Thread error".to_string()))?
+        .map_err(|e| WorkloadError::Database(e.to_string()))?;
+    job.emit(ProgressKind::Finished { namespace, name });
     Ok(())
 }
 
-pub async fn fetch_and_update_all_watched() -> Result<(), String> {
-    let workloads = find_enabled_workloads().await.map_err(|e| e.to_string())?;
+/// Scan every watched workload, returning the ones with an update available.
+/// `bypass_cache` ignores cached tag lists.
+pub async fn fetch_and_update_all_watched(job: &JobHandle, bypass_cache: bool) -> Result<Vec<Workload>, String> {
+    timed_scan(scan_all_watched(job, bypass_cache, None)).await
+}
+
+/// Scan the watched workloads whose schedule is one of `due`.
+pub async fn fetch_and_update_scheduled(job: &JobHandle, due: &[String]) -> Result<Vec<Workload>, String> {
+    timed_scan(scan_all_watched(job, false, Some(due))).await
+}
+
+async fn timed_scan(
+    scan: impl std::future::Future<Output = Result<Vec<Workload>, String>>,
+) -> Result<Vec<Workload>, String> {
+    let started = std::time::Instant::now();
+    let result = scan.await;
+    SCAN_DURATION.set(started.elapsed().as_secs_f64());
+    if result.is_ok() {
+        LAST_SUCCESSFUL_SCAN.set(chrono::Utc::now().timestamp());
+    }
+    result
+}
+
+async fn scan_all_watched(
+    job: &JobHandle,
+    bypass_cache: bool,
+    due: Option<&[String]>,
+) -> Result<Vec<Workload>, String> {
+    let system = load_system();
+    let mut workloads = find_enabled_workloads().await.map_err(|e| e.to_string())?;
     log::info!("Found {} workloads", workloads.len());
+    if let Some(due) = due {
+        let settings = current_settings();
+        workloads.retain(|workload| due.iter().any(|name| name == settings.schedule_of(workload)));
+        log::info!("{} workloads are on schedules {}", workloads.len(), due.join(", "));
+    }
+
+    // Fetch the tags of every repository once, however many workloads use it
+    let mut repositories: Vec<String> = workloads
+        .iter()
+        .filter_map(|workload| repository_of(&workload.image).ok())
+        .collect();
+    repositories.sort();
+    repositories.dedup();
+    log::info!(
+        "Fetching tags for {} repositories, {} at a time",
+        repositories.len(),
+        system.scan_concurrency
+    );
+    let tags: HashMap<String, Result<Vec<ImageTag>, String>> = futures::stream::iter(repositories)
+        .map(|repository| async move {
+            let tags = fetch_tags(&repository, bypass_cache).await;
+            (repository, tags)
+        })
+        .buffer_unordered(system.scan_concurrency.max(1))
+        .collect()
+        .await;
+
     //Update Database
-    //stop after 3
     let scan_id = get_latest_scan_id().unwrap_or(0) + 1;
+    let mut updates = Vec::new();
     for workload in workloads {
-        if let Some(_) = find_latest_tag_for_image(&workload).await {
-            let workload = parse_tags(&workload).await.map_err(|e| e.to_string())?;
-            if workload.update_available.to_string() == "Available" {
-                send_notification(&workload)
-                    .await
-                    .unwrap_or_else(|e| log::error!("Error sending notification: {}", e));
+        job.emit(ProgressKind::Started {
+            namespace: workload.namespace.clone(),
+            name: workload.name.clone(),
+            image: workload.image.clone(),
+        });
+        if let Err(e) = check_patterns(&workload).await {
+            job.emit(ProgressKind::Error {
+                namespace: workload.namespace.clone(),
+                name: workload.name.clone(),
+                error: e,
+            });
+            continue;
+        }
+        let fetched = repository_of(&workload.image).and_then(|repository| {
+            tags.get(&repository)
+                .cloned()
+                .unwrap_or_else(|| Err(format!("No tags fetched for {}", repository)))
+        });
+        let workload = match fetched {
+            Ok(tags) if !tags.is_empty() => {
+                job.emit(ProgressKind::TagsFetched {
+                    namespace: workload.namespace.clone(),
+                    name: workload.name.clone(),
+                    count: tags.len(),
+                });
+                evaluate_workload(workload, tags, &system).await
+            }
+            Ok(_) => {
+                log::info!("No tags found for image: {}", workload.image);
+                workload
             }
-            std::thread::spawn(move || database::client::insert_workload(&workload, scan_id))
-                .join()
-                .map_err(|_| "Thread error".to_string())?
-                .expect("TODO: panic message");
+            Err(e) => {
+                job.emit(ProgressKind::Error {
+                    namespace: workload.namespace.clone(),
+                    name: workload.name.clone(),
+                    error: e,
+                });
+                workload
+            }
+        };
+        if workload.update_available == UpdateStatus::Available {
+            job.emit(ProgressKind::UpdateFound {
+                namespace: workload.namespace.clone(),
+                name: workload.name.clone(),
+                current_version: workload.current_version.clone(),
+                latest_version: workload.latest_version.clone(),
+            });
+            send_notification(&workload)
+                .await
+                .unwrap_or_else(|e| log::error!("Error sending notification: {}", e));
+            updates.push(workload.clone());
+        }
+        let (namespace, name) = (workload.namespace.clone(), workload.name.clone());
+        std::thread::spawn(move || database::client::insert_workload(&workload, scan_id))
+            .join()
+            .map_err(|_| "Thread error".to_string())?
+            .map_err(|e| format!("Failed to store workload {}/{}: {}", namespace, name, e))?;
+        job.emit(ProgressKind::Finished { namespace, name });
+    }
+    Ok(updates)
+}
 
-        } else {
-            log::info!("No tags found for image: {}", workload.image);
-            std::thread::spawn(move || database::client::insert_workload(&workload, scan_id))
-                .join()
-                .map_err(|_| "Thread error".to_string())?
-                .expect("TODO: panic message");
+/// Pick the latest version of a workload from its repository's tags, reading the image labels
+/// of the running and latest tag and linking the release notes in between.
+async fn evaluate_workload(mut workload: Workload, tags: Vec<ImageTag>, system: &System) -> Workload {
+    workload.current_metadata = image_metadata(&workload, &workload.current_version).await;
+    let min_age = min_age(&workload, system);
+    let tags = apply_ignores(&workload, tags);
+    let mut workload = evaluate_candidates(&workload, tags, min_age).await;
+    if workload.update_available == UpdateStatus::Available {
+        workload.latest_metadata = image_metadata(&workload, &workload.latest_version).await;
+    }
+    attach_changelog(workload).await
+}
+
+async fn image_metadata(workload: &Workload, tag: &str) -> Option<ImageMetadata> {
+    get_image_metadata(&workload.image, tag)
+        .await
+        .inspect_err(|e| log::warn!("Failed to read labels of {}:{}: {}", workload.image, tag, e))
+        .ok()
+}
+
+/// The version a workload runs: its tag, or the `org.opencontainers.image.version` label
+/// for tags that are not SemVer, such as `latest`.
+pub fn running_version(workload: &Workload) -> &str {
+    let is_semver = |tag: &str| Version::parse(&strip_tag_lettings(tag)).is_ok();
+    if is_semver(&workload.current_version) {
+        return &workload.current_version;
+    }
+    workload
+        .current_metadata
+        .as_ref()
+        .and_then(|metadata| metadata.version.as_deref())
+        .filter(|version| is_semver(version))
+        .unwrap_or(&workload.current_version)
+}
+
+fn load_system() -> System {
+    current_settings().system.clone()
+}
+
+/// The minimum release age for a workload, from its annotation or the global default.
+fn min_age(workload: &Workload, system: &System) -> Option<chrono::Duration> {
+    let value = workload.min_age.as_ref().or(system.min_age.as_ref())?;
+    parse_duration(value)
+        .inspect_err(|e| log::warn!("Ignoring min_age for {}: {}", workload.name, e))
+        .ok()
+        .filter(|age| *age > chrono::Duration::zero())
+}
+
+// Limits how many candidates are checked against the registry for a single workload
+const MAX_CANDIDATE_LOOKUPS: usize = 5;
+
+/// Like `evaluate_tags`, but skips candidates published less than `min_age` ago and candidates
+/// not published for every architecture in the workload's `arch`. Tags without a registry date
+/// are dated from their image config once they become the candidate. Candidates whose age or
+/// architectures cannot be determined are reported.
+pub async fn evaluate_candidates(
+    workload: &Workload,
+    tags: Vec<ImageTag>,
+    min_age: Option<chrono::Duration>,
+) -> Workload {
+    check_candidates(workload, tags, min_age, &Registry).await
+}
+
+// Lookups made in the registry for a candidate tag
+trait TagLookup {
+    async fn created(&self, image: &str, tag: &str) -> Result<Option<String>, String>;
+    async fn architectures(&self, image: &str, tag: &str) -> Result<Vec<String>, String>;
+}
+
+struct Registry;
+
+impl TagLookup for Registry {
+    async fn created(&self, image: &str, tag: &str) -> Result<Option<String>, String> {
+        get_tag_created(image, tag).await
+    }
+
+    async fn architectures(&self, image: &str, tag: &str) -> Result<Vec<String>, String> {
+        get_tag_architectures(image, tag).await
+    }
+}
+
+async fn check_candidates(
+    workload: &Workload,
+    mut tags: Vec<ImageTag>,
+    min_age: Option<chrono::Duration>,
+    lookup: &impl TagLookup,
+) -> Workload {
+    let required_arches: Vec<&str> = workload
+        .arch
+        .iter()
+        .flat_map(|arch| arch.split(','))
+        .map(str::trim)
+        .filter(|arch| !arch.is_empty())
+        .collect();
+    if min_age.is_none() && required_arches.is_empty() {
+        return evaluate_tags(workload, tags);
+    }
+    let cutoff = min_age.map(|min_age| chrono::Utc::now() - min_age);
+    let too_young = |published: &str| {
+        chrono::DateTime::parse_from_rfc3339(published)
+            .is_ok_and(|published| cutoff.is_some_and(|cutoff| published > cutoff))
+    };
+    tags.retain(|tag| !tag.published.as_deref().is_some_and(too_young));
+
+    for _ in 0..MAX_CANDIDATE_LOOKUPS {
+        let candidate = evaluate_tags(workload, tags.clone());
+        if candidate.update_available != UpdateStatus::Available {
+            return candidate;
+        }
+        let Some(index) = tags.iter().position(|tag| tag.name == candidate.latest_version) else {
+            return candidate;
+        };
+        let tag = &candidate.latest_version;
+
+        if cutoff.is_some() && tags[index].published.is_none() {
+            match lookup.created(&workload.image, tag).await {
+                Ok(Some(created)) if too_young(&created) => {
+                    log::info!(
+                        "Skipping {} for {}, created {} is younger than the minimum age",
+                        tag, workload.name, created
+                    );
+                    tags.remove(index);
+                    continue;
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => log::warn!("No creation date for {}:{}", workload.image, tag),
+                Err(e) => log::warn!("Failed to date {}:{}: {}", workload.image, tag, e),
+            }
         }
+
+        if !required_arches.is_empty() {
+            match lookup.architectures(&workload.image, tag).await {
+                Ok(arches) => {
+                    let missing: Vec<&str> = required_arches
+                        .iter()
+                        .copied()
+                        .filter(|required| !arches.iter().any(|arch| arch == required))
+                        .collect();
+                    if !missing.is_empty() {
+                        log::info!(
+                            "Skipping {} for {}, not published for {}",
+                            tag, workload.name, missing.join(", ")
+                        );
+                        tags.remove(index);
+                        continue;
+                    }
+                }
+                Err(e) => log::warn!("Failed to check architectures of {}:{}: {}", workload.image, tag, e),
+            }
+        }
+        return candidate;
     }
-    Ok(())
+    log::warn!(
+        "No candidate for {} passed the checks after {} lookups, not reporting an update",
+        workload.name, MAX_CANDIDATE_LOOKUPS
+    );
+    evaluate_tags(workload, Vec::new())
 }
 
-pub async fn find_latest_tag_for_image(workload: &Workload) -> Option<String> {
-    match get_tags_for_image(&workload.image).await {
+/// Fetch all tags of an image repository, recording failures for alerting.
+pub async fn fetch_tags(repository: &str, bypass_cache: bool) -> Result<Vec<ImageTag>, String> {
+    // Classify the error before awaiting anything, the boxed error is not Send
+    let result = get_tags_for_image(repository, bypass_cache).await.map_err(|e| {
+        log::error!("Error fetching tags for {}: {}", repository, e);
+        let kind = if is_auth_error(e.as_ref()) {
+            FailureKind::RegistryAuth
+        } else {
+            FailureKind::Scan
+        };
+        (kind, e.to_string())
+    });
+    match result {
         Ok(tags) => {
-            let latest_tag = tags.first()?.clone();
-            log::info!("Latest tag for image {}: {}", workload.image, latest_tag);
-            Some(latest_tag)
+            report_success(FailureKind::Scan, repository).await;
+            report_success(FailureKind::RegistryAuth, repository).await;
+            Ok(tags)
         },
-        Err(e) => {
-            log::error!("Error fetching tags for image {}: {}", workload.image, e);
-            None
+        Err((kind, e)) => {
+            report_failure(kind, repository, &e).await;
+            Err(e)
         },
     }
 }
 
-
 pub async fn test_call() {
     let workloads = find_enabled_workloads().await.unwrap();
     for workload in workloads.iter().take(1) {
         //let workload = workload.clone();
-        let workload = parse_tags(&workload).await.unwrap();
+        let workload = parse_tags(workload).await.unwrap();
         log::info!("Workload: {:?}", workload)
     }
 }
 
-fn strip_tag_lettings(tag: &str) -> String {
-    tag.chars().skip_while(|c| !c.is_digit(10)).collect()
+pub fn strip_tag_lettings(tag: &str) -> String {
+    tag.chars().skip_while(|c| !c.is_ascii_digit()).collect()
+}
+
+/// Classify the jump from `current` to `latest` as a major, minor or patch bump.
+/// Returns `None` when either tag is not valid SemVer or `latest` is not newer.
+pub fn update_level(current: &str, latest: &str) -> Option<UpdateLevel> {
+    let current = Version::parse(&strip_tag_lettings(current)).ok()?;
+    let latest = Version::parse(&strip_tag_lettings(latest)).ok()?;
+    if latest <= current {
+        return None;
+    }
+    if latest.major != current.major {
+        Some(UpdateLevel::Major)
+    } else if latest.minor != current.minor {
+        Some(UpdateLevel::Minor)
+    } else {
+        Some(UpdateLevel::Patch)
+    }
 }
 
 pub async fn parse_tags(workload: &Workload) -> Result<Workload, Box<dyn std::error::Error>> {
-    let mut tags = get_tags_for_image(&workload.image).await?;
-    tags.sort();
+    let tags = get_tags_for_image(&workload.image, false).await?;
+    Ok(evaluate_tags(workload, apply_ignores(workload, tags)))
+}
+
+// Build regexes from comma-separated patterns, failing on the first invalid one
+fn compile_patterns(patterns: &str) -> Result<Vec<Regex>, String> {
+    patterns
+        .split(',')
+        .map(|pattern| Regex::new(pattern).map_err(|e| format!("invalid pattern {:?}: {}", pattern, e)))
+        .collect()
+}
+
+/// Check the include and exclude patterns of a workload before it is scanned. A workload
+/// with an invalid pattern is skipped and the failure recorded, so a broken exclude never
+/// offers the tags it was meant to hide.
+pub async fn check_patterns(workload: &Workload) -> Result<(), String> {
+    let subject = format!("{}/{}", workload.namespace, workload.name);
+    let annotations = [
+        ("slackwatch.include", &workload.include_pattern),
+        ("slackwatch.exclude", &workload.exclude_pattern),
+    ];
+    for (annotation, patterns) in annotations {
+        if let Some(Err(e)) = patterns.as_deref().map(compile_patterns) {
+            let error = format!("{}: {}", annotation, e);
+            log::error!("Skipping {}: {}", subject, error);
+            report_failure(FailureKind::Annotation, &subject, &error).await;
+            return Err(error);
+        }
+    }
+    report_success(FailureKind::Annotation, &subject).await;
+    Ok(())
+}
+
+/// Pick the newest tag above the current version after applying the workload's
+/// include and exclude patterns. Tags with the same version are ordered by push date, and
+/// when the running tag is not a version only tags pushed after it are newer.
+pub fn evaluate_tags(workload: &Workload, mut tags: Vec<ImageTag>) -> Workload {
+    sort_newest_first(&mut tags);
 
     // Include Pattern Handling
     if let Some(include_pattern_str) = &workload.include_pattern {
         log::info!("Include pattern defined, using only include");
         log::info!("Include pattern: {}", include_pattern_str);
 
-        // Build regex from patterns, assuming comma-separated
-        let include_patterns = include_pattern_str
-            .split(",")
-            .map(|pattern| Regex::new(pattern).unwrap()) // Compile each regex
-            .collect::<Vec<Regex>>();
-
-        tags = tags
-            .into_iter()
-            .filter(|tag| include_patterns.iter().any(|regex| regex.is_match(tag)))
-            .collect();
+        // Build regex from patterns, assuming comma-separated. No tag is offered when one is invalid
+        match compile_patterns(include_pattern_str) {
+            Ok(include_patterns) => {
+                tags.retain(|tag| include_patterns.iter().any(|regex| regex.is_match(&tag.name)))
+            }
+            Err(e) => {
+                log::error!("Offering no tags for {}, slackwatch.include: {}", workload.name, e);
+                tags.clear();
+            }
+        }
 
         log::info!("Filtered tags: {:?}", tags);
     }
@@ -122,26 +510,39 @@ pub async fn parse_tags(workload: &Workload) -> Result<Workload, Box<dyn std::er
         log::info!("Exclude pattern defined, using only exclude");
         log::info!("Exclude pattern: {}", exclude_pattern_str);
 
-        // Build regex from patterns, assuming comma-separated
-        let exclude_patterns = exclude_pattern_str
-            .split(",")
-            .map(|pattern| Regex::new(pattern).unwrap()) // Compile each regex
-            .collect::<Vec<Regex>>();
-
-        tags = tags
-            .into_iter()
-            .filter(|tag| exclude_patterns.iter().all(|regex| !regex.is_match(tag)))
-            .collect();
+        // Build regex from patterns, assuming comma-separated. No tag is offered when one is invalid
+        match compile_patterns(exclude_pattern_str) {
+            Ok(exclude_patterns) => {
+                tags.retain(|tag| exclude_patterns.iter().all(|regex| !regex.is_match(&tag.name)))
+            }
+            Err(e) => {
+                log::error!("Offering no tags for {}, slackwatch.exclude: {}", workload.name, e);
+                tags.clear();
+            }
+        }
 
         log::info!("Filtered tags: {:?}", tags);
     }
-    let current_version = Version::parse(&strip_tag_lettings(&workload.current_version))
-        .unwrap_or_else(|_| Version::new(0, 0, 0));
+    let current_version = match Version::parse(&strip_tag_lettings(running_version(workload))) {
+        Ok(version) => version,
+        Err(_) => {
+            let running_published = tags
+                .iter()
+                .find(|tag| tag.name == workload.current_version)
+                .and_then(|tag| tag.published.clone());
+            if let Some(running_published) = running_published {
+                log::info!("{} is not a version, comparing tags pushed after {}", workload.current_version, running_published);
+                tags.retain(|tag| tag.published.as_ref().is_some_and(|published| *published > running_published));
+            }
+            Version::new(0, 0, 0)
+        }
+    };
 
     // Perform SemVer comparison with each tag:
     let mut latest_version = String::new();
+    let mut latest_published = None;
     let mut update_available = UpdateStatus::NotAvailable;
-    for tag in tags {
+    for ImageTag { name: tag, published } in tags {
         if let Ok(tag_version) = Version::parse(&strip_tag_lettings(&tag)) {
             if tag_version > current_version {
                 // tag_version is greater than current_version
@@ -149,11 +550,15 @@ pub async fn parse_tags(workload: &Workload) -> Result<Workload, Box<dyn std::er
                 if latest_version.is_empty() {
                     log::info!("latest_version is empty - setting to tag {}", tag);
                     latest_version = tag.clone();
-                } else if tag_version > Version::parse(&strip_tag_lettings(&latest_version)).unwrap() {
-                    log::info!("Tag {} is newer than {} current latest_version updating", tag, latest_version);
-                    latest_version = tag.clone();
+                    latest_published = published;
+                } else {
+                    let latest = Version::parse(&strip_tag_lettings(&latest_version)).unwrap();
+                    if tag_version > latest || (tag_version == latest && published > latest_published) {
+                        log::info!("Tag {} is newer than {} current latest_version updating", tag, latest_version);
+                        latest_version = tag.clone();
+                        latest_published = published;
+                    }
                 }
-                
             }
         } else {
             // Handle the case where the tag is not a valid SemVer format
@@ -168,7 +573,7 @@ pub async fn parse_tags(workload: &Workload) -> Result<Workload, Box<dyn std::er
         log::info!("Latest version for {}: {}", workload.image, latest_version);
         update_available = UpdateStatus::Available;
     }
-    Ok(Workload {
+    Workload {
         name: workload.name.clone(),
         exclude_pattern: workload.exclude_pattern.clone(),
         git_ops_repo: workload.git_ops_repo.clone(),
@@ -180,5 +585,121 @@ pub async fn parse_tags(workload: &Workload) -> Result<Workload, Box<dyn std::er
         last_scanned: workload.last_scanned.clone(),
         latest_version: latest_version.clone(),
         git_directory: workload.git_directory.clone(),
-    })
+        min_age: workload.min_age.clone(),
+        arch: workload.arch.clone(),
+        source: workload.source.clone(),
+        // Release notes and labels of the new latest version are attached after evaluation
+        changelog_url: None,
+        current_metadata: workload.current_metadata.clone(),
+        latest_metadata: None,
+        schedule: workload.schedule.clone(),
+        auto_upgrade: workload.auto_upgrade.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_min_age_skips_recent_releases() {
+        let workload = Workload::for_test("postgres", "postgres:16.1.0", "16.1.0", "");
+        let days_ago = |days| Some((chrono::Utc::now() - chrono::Duration::days(days)).to_rfc3339());
+        let tags = vec![
+            ImageTag { name: "16.1.0".to_string(), published: days_ago(60) },
+            ImageTag { name: "16.2.0".to_string(), published: days_ago(10) },
+            ImageTag { name: "16.3.0".to_string(), published: days_ago(1) },
+        ];
+
+        let latest = evaluate_candidates(&workload, tags.clone(), None).await;
+        assert_eq!(latest.latest_version, "16.3.0");
+        let latest = evaluate_candidates(&workload, tags, Some(chrono::Duration::days(3))).await;
+        assert_eq!(latest.latest_version, "16.2.0");
+    }
+
+    // Architectures published per tag, 16.3.0 lacks an arm64 image
+    struct Arches;
+
+    impl TagLookup for Arches {
+        async fn created(&self, _image: &str, _tag: &str) -> Result<Option<String>, String> {
+            Ok(None)
+        }
+
+        async fn architectures(&self, _image: &str, tag: &str) -> Result<Vec<String>, String> {
+            match tag {
+                "16.3.0" => Ok(vec!["amd64".to_string()]),
+                "16.4.0" => Err("manifest unknown".to_string()),
+                _ => Ok(vec!["amd64".to_string(), "arm64".to_string()]),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_arch_skips_tags_missing_an_architecture() {
+        let tags = |names: &[&str]| -> Vec<ImageTag> {
+            names.iter().map(|name| ImageTag { name: name.to_string(), published: None }).collect()
+        };
+        let workload = Workload {
+            arch: Some("amd64, arm64".to_string()),
+            ..Workload::for_test("postgres", "postgres:16.1.0", "16.1.0", "")
+        };
+        let latest = check_candidates(&workload, tags(&["16.1.0", "16.2.0", "16.3.0"]), None, &Arches).await;
+        assert_eq!(latest.latest_version, "16.2.0");
+
+        // A candidate whose architectures cannot be read is still offered
+        let latest = check_candidates(&workload, tags(&["16.2.0", "16.3.0", "16.4.0"]), None, &Arches).await;
+        assert_eq!(latest.latest_version, "16.4.0");
+
+        let amd64 = Workload {
+            arch: Some("amd64".to_string()),
+            ..workload
+        };
+        let latest = check_candidates(&amd64, tags(&["16.2.0", "16.3.0"]), None, &Arches).await;
+        assert_eq!(latest.latest_version, "16.3.0");
+    }
+
+    #[test]
+    fn test_invalid_patterns_offer_no_tags() {
+        let tags = |names: &[&str]| -> Vec<ImageTag> {
+            names.iter().map(|name| ImageTag { name: name.to_string(), published: None }).collect()
+        };
+        let workload = Workload {
+            exclude_pattern: Some("-rc,-alpine$".to_string()),
+            ..Workload::for_test("postgres", "postgres:16.1.0", "16.1.0", "")
+        };
+        let latest = evaluate_tags(&workload, tags(&["16.2.0", "16.3.0-rc1", "16.3.0-alpine"]));
+        assert_eq!(latest.latest_version, "16.2.0");
+
+        let broken = Workload {
+            exclude_pattern: Some("-rc,(alpine".to_string()),
+            ..workload
+        };
+        let latest = evaluate_tags(&broken, tags(&["16.2.0", "16.3.0-rc1", "16.3.0-alpine"]));
+        assert_eq!(latest.update_available, UpdateStatus::NotAvailable);
+        assert_eq!(latest.latest_version, "");
+    }
+
+    #[test]
+    fn test_push_dates_order_tags() {
+        let tag = |name: &str, published: &str| ImageTag {
+            name: name.to_string(),
+            published: Some(published.to_string()),
+        };
+        let tags = vec![
+            tag("2.0.0", "2019-03-01T00:00:00Z"),
+            tag("1.30.0", "2024-05-01T00:00:00Z"),
+            tag("stable", "2024-05-01T00:00:00Z"),
+            tag("1.31.0-rc1", "2024-06-01T00:00:00Z"),
+            tag("1.31.0", "2024-06-02T00:00:00Z"),
+        ];
+        // A running version is compared by version whatever the push dates
+        let workload = Workload::for_test("app", "app:1.30.0", "1.30.0", "");
+        let latest = evaluate_tags(&workload, tags.clone());
+        assert_eq!(latest.latest_version, "2.0.0");
+
+        // Without a version, tags pushed before the running one are not newer
+        let workload = Workload::for_test("app", "app:stable", "stable", "");
+        let latest = evaluate_tags(&workload, tags);
+        assert_eq!(latest.latest_version, "1.31.0");
+    }
 }